@@ -1,16 +1,18 @@
 use std::env;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use anyhow::Error;
-use jsonwebtoken::{encode, EncodingKey, Header};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use strum::EnumString;
 use crate::game::GameId;
 
 pub const TOKEN_EXPIRY: u64 = 60 * 60;
 // expiry doesn't really matter as players can just POST /game/join again to get a new token
 // only useful for reconnecting if connection drops
 pub const WEBSOCKET_TOKEN_EXPIRY: u64 = 60 * 60 * 24;
-pub const HTTP_AUDIENCE: &'static str = "cards";
-pub const WEBSOCKET_AUDIENCE: &'static str = "websocket";
+pub const HTTP_AUDIENCE: &str = "cards";
+pub const WEBSOCKET_AUDIENCE: &str = "websocket";
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all="camelCase")]
@@ -21,6 +23,46 @@ pub struct Claims {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub game_id: Option<GameId>,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub tier: Tier,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, EnumString, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Role {
+    #[default]
+    Player,
+    Spectator,
+    /// A shared screen showing the table, which the owner can give their controls to
+    Table,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, EnumString, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Tier {
+    #[default]
+    Free,
+    Premium,
+}
+
+impl Tier {
+    pub fn max_players(self) -> usize {
+        match self {
+            Tier::Free => 8,
+            Tier::Premium => 32,
+        }
+    }
+
+    pub fn max_spectators(self) -> usize {
+        match self {
+            Tier::Free => 10,
+            Tier::Premium => 100,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -30,10 +72,14 @@ pub struct AuthorizationContext {
     pub expires: usize,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub game_id: Option<GameId>
+    pub game_id: Option<GameId>,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub tier: Tier,
 }
 
-pub async fn generate_jwt(user_id: &str, expiry: u64, game_id: Option<&GameId>) -> Result<String, Error> {
+pub async fn generate_jwt(user_id: &str, expiry: u64, game_id: Option<&GameId>, role: Role) -> Result<String, Error> {
     let expiration = SystemTime::now()
         .duration_since(UNIX_EPOCH)?
         .checked_add(Duration::from_secs(expiry))
@@ -44,7 +90,10 @@ pub async fn generate_jwt(user_id: &str, expiry: u64, game_id: Option<&GameId>)
         sub: user_id.to_string(),
         exp: expiration,
         aud: game_id.as_ref().map_or("cards", |_| "websocket").to_string(),
-        game_id: game_id.cloned()
+        game_id: game_id.cloned(),
+        role,
+        // todo issue premium tokens once players have accounts to upgrade
+        tier: Tier::Free,
     };
 
     let secret = env::var("JWT_SECRET").expect("expected jwt secret");
@@ -52,3 +101,21 @@ pub async fn generate_jwt(user_id: &str, expiry: u64, game_id: Option<&GameId>)
 
     Ok(encode(&Header::default(), &claims, &encoding_key)?)
 }
+
+pub fn bearer_token(header: Option<&str>) -> Option<&str> {
+    match header {
+        Some(header) if header.starts_with("Bearer ") => {
+            Some(header.trim_start_matches("Bearer ").trim())
+        }
+        _ => None,
+    }
+}
+
+/// Decodes the token, only accepting it if it is valid for the given audience
+pub fn validate_token(token: &str, decoding_key: &DecodingKey, audience: &str) -> Option<Claims> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[audience]);
+    decode::<Claims>(token, decoding_key, &validation)
+        .map(|t| t.claims)
+        .ok()
+}
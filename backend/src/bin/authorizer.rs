@@ -3,9 +3,9 @@ use aws_lambda_events::apigw::{
     ApiGatewayCustomAuthorizerResponse,
 };
 use aws_lambda_events::iam::{IamPolicyEffect, IamPolicyStatement};
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use jsonwebtoken::DecodingKey;
 use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
-use multiplayer_cards::auth::{AuthorizationContext, Claims, HTTP_AUDIENCE, WEBSOCKET_AUDIENCE};
+use multiplayer_cards::auth::{bearer_token, validate_token, AuthorizationContext, Claims, HTTP_AUDIENCE, WEBSOCKET_AUDIENCE};
 use serde_json::json;
 
 #[tokio::main]
@@ -35,27 +35,19 @@ pub async fn function_handler(
 
     let method_arn = event.payload.method_arn.expect("no arn given for auth");
 
-    let Some(token) = (match auth_header {
-        Some(header) if header.starts_with("Bearer ") => {
-            Some(header.trim_start_matches("Bearer ").trim())
-        }
-        _ => None,
-    }) else {
+    let Some(token) = bearer_token(auth_header) else {
         return Ok(generate_response(IamPolicyEffect::Deny, method_arn, None));
     };
 
-
-    let mut validation = Validation::default();
-    if method_arn == websocket_arn {
-        validation.set_audience(&[WEBSOCKET_AUDIENCE])
+    let audience = if method_arn == websocket_arn {
+        WEBSOCKET_AUDIENCE
     } else {
-        validation.set_audience(&[HTTP_AUDIENCE])
-    }
+        HTTP_AUDIENCE
+    };
 
-    decode::<Claims>(token, &decoding_key, &validation)
-        .map(|t| t.claims)
+    validate_token(token, decoding_key, audience)
         .map_or_else(
-            |_| {
+            || {
                 Ok(generate_response(
                     IamPolicyEffect::Deny,
                     method_arn.clone(),
@@ -93,7 +85,9 @@ pub fn generate_response(
                 json!(AuthorizationContext {
                     uuid: claims.sub,
                     expires: claims.exp,
-                    game_id: claims.game_id
+                    game_id: claims.game_id,
+                    role: claims.role,
+                    tier: claims.tier,
                 })
             })
             .unwrap_or_else(|| json!({})),
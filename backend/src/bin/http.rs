@@ -5,40 +5,20 @@ use lambda_http::{
     run, service_fn, tracing, Body, Error, IntoResponse, Request, RequestExt, RequestPayloadExt,
     Response,
 };
-use multiplayer_cards::auth::{generate_jwt, TOKEN_EXPIRY, WEBSOCKET_TOKEN_EXPIRY};
-use multiplayer_cards::db_utils::RefreshToken;
-use multiplayer_cards::game::Game;
-use multiplayer_cards::requests::{CreateGameRequest, JoinGameRequest, JoinGameResponse};
+use multiplayer_cards::auth::bearer_token;
+use multiplayer_cards::handlers::http::{create_game, game_list, game_log, guest_login, join_game, refresh_token};
+use multiplayer_cards::requests::{CreateGameRequest, GameListRequest, GameLogRequest, JoinGameRequest};
 use multiplayer_cards::utils::AuthorizerUtils;
 use multiplayer_cards::{Services, WebsocketError};
-use serde::{Deserialize, Serialize};
 use serde_json::json;
-use uuid::Uuid;
-
-#[derive(Serialize, Deserialize)]
-pub struct AuthResponse {
-    pub access_token: String,
-    pub expires_in: u64,
-    pub refresh_token: String,
-}
 
 async fn guest_login_handler(
     _event: Request,
     services: &Services,
 ) -> Result<Response<Body>, Error> {
-    let uuid = Uuid::new_v4().to_string();
-    let access_token = generate_jwt(&uuid, TOKEN_EXPIRY, None).await?;
-    let refresh_token = generate_refresh_token();
-
-    let _ = services.put::<RefreshToken>(&refresh_token, &uuid).await?;
-
-    Ok(json!(AuthResponse {
-        access_token,
-        expires_in: TOKEN_EXPIRY,
-        refresh_token
-    })
-    .into_response()
-    .await)
+    Ok(json!(guest_login(services).await?)
+        .into_response()
+        .await)
 }
 
 async fn refresh_token_handler(
@@ -50,47 +30,50 @@ async fn refresh_token_handler(
         .get("Authorization")
         .and_then(|h| h.to_str().ok());
 
-    let Some(token_str) = (match auth_header {
-        Some(header) if header.starts_with("Bearer ") => {
-            Some(header.trim_start_matches("Bearer ").trim())
-        }
-        _ => None,
-    }) else {
+    let Some(token_str) = bearer_token(auth_header) else {
         return Ok((StatusCode::UNAUTHORIZED, "Missing authorization header")
             .into_response()
             .await);
     };
 
-    let Ok(uuid) = services
-        .delete::<RefreshToken>(&token_str.to_string(), None)
-        .await
-    else {
+    let Some(response) = refresh_token(services, token_str).await? else {
         return Ok((StatusCode::UNAUTHORIZED, "Unauthorized")
             .into_response()
             .await);
     };
-    let access_token = generate_jwt(&uuid, TOKEN_EXPIRY, None).await?;
-    let new_refresh_token = generate_refresh_token();
-    let _ = services
-        .put::<RefreshToken>(&new_refresh_token, &uuid)
-        .await?;
-
-    Ok(json!(AuthResponse {
-        access_token,
-        expires_in: TOKEN_EXPIRY,
-        refresh_token: new_refresh_token
-    })
-    .into_response()
-    .await)
+
+    Ok(json!(response)
+        .into_response()
+        .await)
 }
 
 async fn create_game_handler(event: Request, services: &Services) -> Result<Response<Body>, Error> {
+    let context = event.request_context();
+    let authorizer = context.authorizer().unwrap();
+    let uuid = authorizer.unwrap_field("uuid");
+    let tier = authorizer.unwrap_field("tier").parse().unwrap_or_default();
+    let Ok(Some(request)) = event.payload::<CreateGameRequest>() else {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            json!(WebsocketError::InvalidRequest("error parsing json")),
+        )
+            .into_response()
+            .await);
+    };
+
+    match create_game(services, uuid, tier, request).await {
+        Ok(response) => Ok(json!(response).into_response().await),
+        Err(error) => error_response(error).await,
+    }
+}
+
+async fn join_game_handler(event: Request, services: &Services) -> Result<Response<Body>, Error> {
     let uuid = event
         .request_context()
         .authorizer()
         .unwrap()
         .unwrap_field("uuid");
-    let Ok(Some(request)) = event.payload::<CreateGameRequest>() else {
+    let Ok(Some(request)) = event.payload::<JoinGameRequest>() else {
         return Ok((
             StatusCode::BAD_REQUEST,
             json!(WebsocketError::InvalidRequest("error parsing json")),
@@ -99,57 +82,59 @@ async fn create_game_handler(event: Request, services: &Services) -> Result<Resp
             .await);
     };
 
-    // If player is currently in a game they will be removed when reconnecting to the websocket
-    // if let Some(_) = services.get::<Player>(&uuid).await {
-    //     Ok((StatusCode::BAD_REQUEST, json!(WebsocketError::AlreadyInGame)).into_response().await)
-    // } else {
-    let game = Game::new(services, uuid.clone(), request.deck_type).await?;
-    let token = generate_jwt(uuid.as_str(), WEBSOCKET_TOKEN_EXPIRY, Some(&game.id)).await?;
-    Ok(json!(JoinGameResponse {
-        game_id: game.id,
-        token
-    })
-    .into_response()
-    .await)
-    // }
+    match join_game(services, uuid, request).await {
+        Ok(response) => Ok(json!(response).into_response().await),
+        Err(error) => error_response(error).await,
+    }
 }
 
-async fn join_game_handler(event: Request, services: &Services) -> Result<Response<Body>, Error> {
+async fn game_log_handler(event: Request, services: &Services) -> Result<Response<Body>, Error> {
     let uuid = event
         .request_context()
         .authorizer()
         .unwrap()
         .unwrap_field("uuid");
-    let Ok(Some(request)) = event.payload::<JoinGameRequest>() else {
+    let Some(game_id) = event.query_string_parameters().first("gameId").map(str::to_string) else {
         return Ok((
             StatusCode::BAD_REQUEST,
-            json!(WebsocketError::InvalidRequest("error parsing json")),
+            json!(WebsocketError::InvalidRequest("missing gameId query parameter")),
         )
             .into_response()
             .await);
     };
-    let game = services.get::<Game>(&request.game_id).await;
-    if let Some(mut game) = game {
-        let token = generate_jwt(uuid.as_str(), WEBSOCKET_TOKEN_EXPIRY, Some(&game.id)).await?;
-        game.add_authorized_player(services, uuid).await?;
-        Ok(json!(JoinGameResponse {
-            game_id: game.id,
-            token
-        })
-        .into_response()
-        .await)
-    } else {
-        Ok((
+
+    match game_log(services, uuid, GameLogRequest { game_id }).await {
+        Ok(response) => Ok(json!(response).into_response().await),
+        Err(error) => error_response(error).await,
+    }
+}
+
+async fn game_list_handler(event: Request, services: &Services) -> Result<Response<Body>, Error> {
+    let query = event.query_string_parameters();
+    let cursor = query.first("cursor").map(str::to_string);
+    let Ok(limit) = query.first("limit").map(str::parse::<usize>).transpose() else {
+        return Ok((
             StatusCode::BAD_REQUEST,
-            json!(WebsocketError::NonExistentGame(request.game_id.clone())),
+            json!(WebsocketError::InvalidRequest("limit must be a number")),
         )
             .into_response()
-            .await)
+            .await);
+    };
+
+    match game_list(services, GameListRequest { cursor, limit }).await {
+        Ok(response) => Ok(json!(response).into_response().await),
+        Err(error) => error_response(error).await,
     }
 }
 
-pub fn generate_refresh_token() -> String {
-    format!("refresh_{}", Uuid::new_v4())
+/// Service errors are returned as a failed invocation, anything else is the client's fault
+async fn error_response(error: WebsocketError) -> Result<Response<Body>, Error> {
+    match error {
+        WebsocketError::ServiceError(message) => Err(anyhow!(message).into()),
+        error => Ok((StatusCode::BAD_REQUEST, json!(error))
+            .into_response()
+            .await),
+    }
 }
 
 #[tokio::main]
@@ -172,6 +157,8 @@ async fn main() -> Result<(), Error> {
             // Authorized routes
             "/game/create" => create_game_handler(event, &services).await,
             "/game/join" => join_game_handler(event, &services).await,
+            "/game/log" => game_log_handler(event, &services).await,
+            "/game/list" => game_list_handler(event, &services).await,
             _ => Ok(Response::builder()
                 .status(400)
                 .body(format!("Unknown route {route_key}").into())?),
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Query, State, WebSocketUpgrade};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use jsonwebtoken::DecodingKey;
+use lambda_http::tracing;
+use multiplayer_cards::auth::{bearer_token, validate_token, Claims, Role, HTTP_AUDIENCE, WEBSOCKET_AUDIENCE};
+use multiplayer_cards::broadcast::{Broadcaster, ChannelBroadcaster, ConnectionId};
+use multiplayer_cards::encoding::Encoding;
+use multiplayer_cards::handlers::http::{create_game, game_list, game_log, guest_login, join_game, refresh_token};
+use multiplayer_cards::game::{Game, GameSettings};
+use multiplayer_cards::handlers::websocket::{connect, disconnect, handle_request, reclaim_expired};
+use multiplayer_cards::requests::{CreateGameRequest, GameListRequest, GameLogRequest, JoinGameRequest, WebsocketRequest, WebsocketResponse};
+use multiplayer_cards::store::{DynamoStore, MemoryStore, Store};
+use multiplayer_cards::{Services, WebsocketError};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Games are kept in memory unless `TABLE_NAME` is set, in which case DynamoDB is used
+#[derive(Clone)]
+struct ServerState {
+    services: Arc<Services>,
+    broadcaster: Arc<ChannelBroadcaster>,
+    decoding_key: DecodingKey,
+}
+
+async fn guest_login_route(State(state): State<ServerState>) -> Response {
+    match guest_login(&state.services).await {
+        Ok(response) => Json(response).into_response(),
+        Err(error) => internal_error(error.to_string()),
+    }
+}
+
+async fn refresh_token_route(State(state): State<ServerState>, headers: HeaderMap) -> Response {
+    let auth_header = headers.get("Authorization").and_then(|h| h.to_str().ok());
+    let Some(token) = bearer_token(auth_header) else {
+        return (StatusCode::UNAUTHORIZED, "Missing authorization header").into_response();
+    };
+
+    match refresh_token(&state.services, token).await {
+        Ok(Some(response)) => Json(response).into_response(),
+        Ok(None) => (StatusCode::UNAUTHORIZED, "Unauthorized").into_response(),
+        Err(error) => internal_error(error.to_string()),
+    }
+}
+
+async fn create_game_route(State(state): State<ServerState>, headers: HeaderMap, body: Bytes) -> Response {
+    let Some(claims) = authorize(&headers, &state.decoding_key, HTTP_AUDIENCE) else {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    };
+    let Ok(request) = serde_json::from_slice::<CreateGameRequest>(&body) else {
+        return invalid_json();
+    };
+
+    match create_game(&state.services, claims.sub, claims.tier, request).await {
+        Ok(response) => Json(response).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn join_game_route(State(state): State<ServerState>, headers: HeaderMap, body: Bytes) -> Response {
+    let Some(claims) = authorize(&headers, &state.decoding_key, HTTP_AUDIENCE) else {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    };
+    let Ok(request) = serde_json::from_slice::<JoinGameRequest>(&body) else {
+        return invalid_json();
+    };
+
+    match join_game(&state.services, claims.sub, request).await {
+        Ok(response) => Json(response).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn game_log_route(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(claims) = authorize(&headers, &state.decoding_key, HTTP_AUDIENCE) else {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    };
+    let Some(game_id) = query.get("gameId").cloned() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!(WebsocketError::InvalidRequest("missing gameId query parameter"))),
+        )
+            .into_response();
+    };
+
+    match game_log(&state.services, claims.sub, GameLogRequest { game_id }).await {
+        Ok(response) => Json(response).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn game_list_route(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    Query(query): Query<GameListRequest>,
+) -> Response {
+    if authorize(&headers, &state.decoding_key, HTTP_AUDIENCE).is_none() {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+    match game_list(&state.services, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn websocket_route(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let Some(Claims { sub: uuid, game_id: Some(game_id), role, .. }) =
+        authorize(&headers, &state.decoding_key, WEBSOCKET_AUDIENCE)
+    else {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    };
+
+    let Some(encoding) = Encoding::from_query(query.get("encoding").map(String::as_str)) else {
+        return (StatusCode::BAD_REQUEST, "unsupported encoding").into_response();
+    };
+
+    let conn_id = ConnectionId::new(Uuid::new_v4().to_string(), encoding);
+    match connect(&state.services, &uuid, &game_id, &conn_id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::GONE, "game closed").into_response(),
+        Err(error) => return internal_error(error.to_string()),
+    }
+
+    upgrade.on_upgrade(async move |socket| {
+        serve_socket(socket, state, uuid, game_id, role, conn_id).await
+    })
+}
+
+async fn serve_socket(
+    mut socket: WebSocket,
+    state: ServerState,
+    uuid: String,
+    game_id: String,
+    role: Role,
+    conn_id: ConnectionId,
+) {
+    let mut responses = state.broadcaster.connect(conn_id.id.clone());
+
+    loop {
+        tokio::select! {
+            response = responses.recv() => match response {
+                Some(response) => {
+                    let Some(message) = encode_response(conn_id.encoding, &response) else {
+                        continue;
+                    };
+                    if socket.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                // The connection has been deleted by the game
+                None => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+            message = socket.recv() => match message {
+                Some(Ok(message @ (Message::Text(_) | Message::Binary(_)))) => {
+                    // Decoded the same way as through API Gateway, based on the type of frame
+                    let request = match &message {
+                        Message::Binary(bytes) => WebsocketRequest::decode(Encoding::MessagePack, bytes),
+                        _ => WebsocketRequest::decode(Encoding::Json, message.to_text().unwrap_or_default().as_bytes()),
+                    };
+                    let result = handle_request(&state.services, request, uuid.clone(), game_id.clone(), role, &conn_id).await;
+                    if let Err(error) = result {
+                        tracing::error!("failed to handle message: {error}");
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = state.broadcaster.delete_connection(&conn_id.id).await;
+    if let Err(error) = disconnect(&state.services, uuid, &game_id, &conn_id.id).await {
+        tracing::error!("failed to disconnect: {error}");
+    }
+
+    // Return the player's hand once the grace period has passed, unless they reconnect first
+    if let Some(Game { settings: GameSettings { reclaim_after: Some(reclaim_after), .. }, .. }) =
+        state.services.get::<Game>(&game_id).await
+    {
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(reclaim_after)).await;
+            if let Err(error) = reclaim_expired(&state.services, &game_id).await {
+                tracing::error!("failed to reclaim hands: {error}");
+            }
+        });
+    }
+}
+
+fn encode_response(encoding: Encoding, response: &WebsocketResponse) -> Option<Message> {
+    let bytes = encoding.encode(response).ok()?;
+    if encoding.is_binary() {
+        Some(Message::Binary(bytes.into()))
+    } else {
+        String::from_utf8(bytes).ok().map(|text| Message::Text(text.into()))
+    }
+}
+
+fn authorize(headers: &HeaderMap, decoding_key: &DecodingKey, audience: &str) -> Option<Claims> {
+    let auth_header = headers.get("Authorization").and_then(|h| h.to_str().ok());
+    bearer_token(auth_header).and_then(|token| validate_token(token, decoding_key, audience))
+}
+
+fn invalid_json() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!(WebsocketError::InvalidRequest("error parsing json"))),
+    )
+        .into_response()
+}
+
+fn error_response(error: WebsocketError) -> Response {
+    match error {
+        WebsocketError::ServiceError(message) => internal_error(message),
+        error => (StatusCode::BAD_REQUEST, Json(json!(error))).into_response(),
+    }
+}
+
+fn internal_error(message: String) -> Response {
+    tracing::error!("{message}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    tracing::init_default_subscriber();
+
+    let secret = env::var("JWT_SECRET").expect("jwt secret not set");
+    let address = env::var("BIND_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
+
+    let store: Box<dyn Store> = match env::var("TABLE_NAME") {
+        Ok(table_name) => {
+            let shared_conf = aws_config::load_from_env().await;
+            Box::new(DynamoStore::new(aws_sdk_dynamodb::Client::new(&shared_conf), table_name))
+        }
+        Err(_) => Box::new(MemoryStore::default()),
+    };
+    let broadcaster = Arc::new(ChannelBroadcaster::default());
+
+    let state = ServerState {
+        services: Arc::new(Services {
+            store,
+            broadcaster: broadcaster.clone(),
+        }),
+        broadcaster,
+        decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+    };
+
+    let router = Router::new()
+        .route("/auth/guest", post(guest_login_route))
+        .route("/auth/refresh", post(refresh_token_route))
+        // Authorized routes
+        .route("/game/create", post(create_game_route))
+        .route("/game/join", post(join_game_route))
+        .route("/game/log", get(game_log_route))
+        .route("/game/list", get(game_list_route))
+        .route("/ws", get(websocket_route))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    tracing::info!("listening on {address}");
+    axum::serve(listener, router).await?;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::fmt::{Display, Formatter};
+use anyhow::Error;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use crate::encoding::Encoding;
+use crate::requests::WebsocketResponse;
+use crate::Services;
+
+mod apigw;
+mod channel;
+mod recording;
+
+pub use apigw::*;
+pub use channel::*;
+pub use recording::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "StoredConnectionId")]
+pub struct ConnectionId {
+    pub id: String,
+    pub encoding: Encoding,
+}
+
+/// Connections were stored as a bare id before they had an encoding, when they were all JSON
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredConnectionId {
+    Id(String),
+    Connection {
+        id: String,
+        #[serde(default)]
+        encoding: Encoding,
+    },
+}
+
+impl From<StoredConnectionId> for ConnectionId {
+    fn from(stored: StoredConnectionId) -> Self {
+        match stored {
+            StoredConnectionId::Id(id) => Self::new(id, Encoding::Json),
+            StoredConnectionId::Connection { id, encoding } => Self::new(id, encoding),
+        }
+    }
+}
+
+impl ConnectionId {
+    pub fn new(id: impl Into<String>, encoding: Encoding) -> Self {
+        Self { id: id.into(), encoding }
+    }
+}
+
+impl Display for ConnectionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+#[async_trait]
+pub trait Broadcaster: Send + Sync {
+    async fn send(&self, conn_id: &ConnectionId, response: &WebsocketResponse) -> Result<(), Error>;
+
+    /// Failing to reach a single connection doesn't stop the response being sent to the rest
+    async fn send_batch(&self, conn_ids: &[&ConnectionId], response: &WebsocketResponse) -> Result<(), Error> {
+        for conn_id in conn_ids {
+            let _ = self.send(conn_id, response).await;
+        }
+        Ok(())
+    }
+
+    async fn delete_connection(&self, conn_id: &str) -> Result<(), Error>;
+}
+
+impl Services {
+    pub async fn send(&self, conn_id: &ConnectionId, response: &WebsocketResponse) -> Result<(), Error> {
+        self.broadcaster.send(conn_id, response).await
+    }
+
+    pub async fn send_batch<'a, I>(&self, connections: I, response: &WebsocketResponse) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a ConnectionId>,
+    {
+        let conn_ids: Vec<&ConnectionId> = connections.into_iter().collect();
+        self.broadcaster.send_batch(&conn_ids, response).await
+    }
+
+    pub async fn delete_connection(&self, conn_id: &str) -> Result<(), Error> {
+        self.broadcaster.delete_connection(conn_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::broadcast::ConnectionId;
+    use crate::encoding::Encoding;
+
+    #[test]
+    fn connections_stored_as_a_bare_id_are_json() {
+        let conn_id: ConnectionId = serde_json::from_value(json!("abc")).unwrap();
+        assert_eq!(conn_id, ConnectionId::new("abc", Encoding::Json));
+
+        let conn_id = ConnectionId::new("abc", Encoding::MessagePack);
+        assert_eq!(serde_json::from_value::<ConnectionId>(serde_json::to_value(&conn_id).unwrap()).unwrap(), conn_id);
+        let conn_id: ConnectionId = serde_json::from_value(json!({ "id": "abc" })).unwrap();
+        assert_eq!(conn_id.encoding, Encoding::Json);
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use aws_sdk_apigatewaymanagement::primitives::Blob;
+use aws_sdk_apigatewaymanagement::Client;
+use crate::broadcast::{Broadcaster, ConnectionId};
+use crate::requests::WebsocketResponse;
+
+pub struct ApiGatewayBroadcaster {
+    client: Client,
+}
+
+impl ApiGatewayBroadcaster {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl Broadcaster for ApiGatewayBroadcaster {
+    async fn send(&self, conn_id: &ConnectionId, response: &WebsocketResponse) -> Result<(), Error> {
+        self.client
+            .post_to_connection()
+            .connection_id(&conn_id.id)
+            .data(Blob::new(conn_id.encoding.encode(response)?))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn send_batch(&self, conn_ids: &[&ConnectionId], response: &WebsocketResponse) -> Result<(), Error> {
+        // Only serialize once for each encoding
+        let mut blobs = HashMap::new();
+        for conn_id in conn_ids {
+            let blob = match blobs.entry(conn_id.encoding) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(Blob::new(conn_id.encoding.encode(response)?)),
+            };
+            let _ = self.client
+                .post_to_connection()
+                .connection_id(&conn_id.id)
+                .data(blob.clone())
+                .send()
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn delete_connection(&self, conn_id: &str) -> Result<(), Error> {
+        self.client
+            .delete_connection()
+            .connection_id(conn_id)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Used when no websocket endpoint is configured, e.g. by the http function. Every call fails
+pub struct NoBroadcaster;
+
+#[async_trait]
+impl Broadcaster for NoBroadcaster {
+    async fn send(&self, _conn_id: &ConnectionId, _response: &WebsocketResponse) -> Result<(), Error> {
+        Err(anyhow!("no websocket endpoint configured"))
+    }
+
+    async fn delete_connection(&self, _conn_id: &str) -> Result<(), Error> {
+        Err(anyhow!("no websocket endpoint configured"))
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use crate::broadcast::{Broadcaster, ConnectionId};
+use crate::requests::WebsocketResponse;
+
+/// For servers that hold the websocket connections themselves
+#[derive(Default)]
+pub struct ChannelBroadcaster {
+    connections: Mutex<HashMap<String, UnboundedSender<WebsocketResponse>>>,
+}
+
+impl ChannelBroadcaster {
+    /// The receiver is closed once the connection is deleted
+    pub fn connect(&self, conn_id: String) -> UnboundedReceiver<WebsocketResponse> {
+        let (sender, receiver) = unbounded_channel();
+        self.connections.lock().unwrap().insert(conn_id, sender);
+        receiver
+    }
+}
+
+#[async_trait]
+impl Broadcaster for ChannelBroadcaster {
+    async fn send(&self, conn_id: &ConnectionId, response: &WebsocketResponse) -> Result<(), Error> {
+        self.connections.lock().unwrap()
+            .get(&conn_id.id)
+            .ok_or_else(|| anyhow!("connection `{conn_id}` does not exist"))?
+            .send(response.clone())
+            .map_err(|_| anyhow!("connection `{conn_id}` has closed"))
+    }
+
+    async fn delete_connection(&self, conn_id: &str) -> Result<(), Error> {
+        self.connections.lock().unwrap()
+            .remove(conn_id)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("connection `{conn_id}` does not exist"))
+    }
+}
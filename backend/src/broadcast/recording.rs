@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use anyhow::Error;
+use async_trait::async_trait;
+use crate::broadcast::{Broadcaster, ConnectionId};
+use crate::requests::WebsocketResponse;
+
+/// Keeps every response sent to each connection so they can be inspected later, e.g. in tests
+#[derive(Default)]
+pub struct RecordingBroadcaster {
+    sent: Mutex<HashMap<String, Vec<WebsocketResponse>>>,
+    deleted: Mutex<Vec<String>>,
+}
+
+impl RecordingBroadcaster {
+    pub fn responses(&self, conn_id: &str) -> Vec<WebsocketResponse> {
+        self.sent.lock().unwrap().get(conn_id).cloned().unwrap_or_default()
+    }
+
+    pub fn take_responses(&self, conn_id: &str) -> Vec<WebsocketResponse> {
+        self.sent.lock().unwrap().remove(conn_id).unwrap_or_default()
+    }
+
+    pub fn is_deleted(&self, conn_id: &str) -> bool {
+        self.deleted.lock().unwrap().iter().any(|deleted| deleted == conn_id)
+    }
+}
+
+#[async_trait]
+impl Broadcaster for RecordingBroadcaster {
+    async fn send(&self, conn_id: &ConnectionId, response: &WebsocketResponse) -> Result<(), Error> {
+        self.sent.lock().unwrap()
+            .entry(conn_id.id.clone())
+            .or_default()
+            .push(response.clone());
+        Ok(())
+    }
+
+    async fn delete_connection(&self, conn_id: &str) -> Result<(), Error> {
+        self.deleted.lock().unwrap().push(conn_id.to_string());
+        Ok(())
+    }
+}
@@ -1,9 +1,9 @@
 use std::fmt::Display;
 use anyhow::{anyhow, Error};
-use aws_sdk_dynamodb::operation::put_item::PutItemOutput;
-use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
 use serde::de::DeserializeOwned;
 use serde::{Serialize};
+use crate::broadcast::ConnectionId;
+use crate::store::{Append, IndexEntry, Item};
 use crate::Services;
 
 #[macro_export] macro_rules! db_entry {
@@ -27,39 +27,73 @@ pub trait Key {
     fn key(key: &Self::Key) -> String {
         format!("{}:{key}", Self::prefix())
     }
+
+    /// Versioned values are only stored if the currently stored version is the one before
+    fn version(_value: &Self::Value) -> Option<u64> {
+        None
+    }
+
+    /// For values that need to be found without their key
+    fn index(_value: &Self::Value) -> Result<Option<IndexEntry>, Error> {
+        Ok(None)
+    }
+
+    fn item(key: &Self::Key, value: &Self::Value) -> Result<Item, Error> {
+        Ok(Item {
+            key: Self::key(key),
+            value: serde_json::to_string(value)?,
+            version: Self::version(value),
+            index: Self::index(value)?,
+        })
+    }
+
+    fn append(key: &Self::Key, value: &Self::Value) -> Result<Append, Error> {
+        Ok(Append {
+            key: Self::key(key),
+            value: serde_json::to_string(value)?,
+        })
+    }
 }
 
 db_entry!(RefreshToken, String, String, "refresh_token");
-db_entry!(Connection, String, String, "connection");
+db_entry!(Connection, String, ConnectionId, "connection");
 
 impl Services {
     pub async fn put<T: Key>(
         &self,
         key: &T::Key,
         value: &T::Value,
-    ) -> Result<PutItemOutput, Error> {
-        let key_av = AttributeValue::S(T::key(key));
-        let value = AttributeValue::S(serde_json::to_string(&value)?);
-        self.db.put_item()
-            .table_name(&self.table_name)
-            .item("pk", key_av)
-            .item("content", value)
-            .send()
-            .await
-            .map_err(|err| err.into())
+    ) -> Result<(), Error> {
+        self.store.put(T::item(key, value)?).await
+    }
+
+    pub async fn put_all(&self, items: Vec<Item>, appends: Vec<Append>) -> Result<(), Error> {
+        self.store.put_all(items, appends).await
     }
 
     pub async fn get<T: Key>(&self, key: &T::Key) -> Option<T::Value> {
-        let key_av = AttributeValue::S(T::key(key));
-        let response = self.db
-            .get_item()
-            .table_name(&self.table_name)
-            .key("pk", key_av)
-            .send()
-            .await
-            .unwrap();
-        let value = response.item()?.get("content").unwrap().as_s().unwrap();
-        serde_json::from_str::<T::Value>(value).ok()
+        let value = self.store.get(&T::key(key)).await.unwrap()?;
+        serde_json::from_str::<T::Value>(&value).ok()
+    }
+
+    pub async fn query<T: DeserializeOwned>(
+        &self,
+        partition: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<T>, Option<String>), Error> {
+        let (values, next) = self.store.query(partition, cursor, limit).await?;
+        let values = values.iter()
+            .map(|value| Ok(serde_json::from_str::<T>(value)?))
+            .collect::<Result<_, Error>>()?;
+        Ok((values, next))
+    }
+
+    pub async fn list<T: Key>(&self, key: &T::Key) -> Result<Vec<T::Value>, Error> {
+        self.store.list(&T::key(key)).await?
+            .iter()
+            .map(|value| Ok(serde_json::from_str::<T::Value>(value)?))
+            .collect()
     }
 
     pub async fn delete<T: Key>(
@@ -67,33 +101,17 @@ impl Services {
         key: &T::Key,
         value: Option<&T::Value>,
     ) -> Result<T::Value, Error> {
-        let key_av = AttributeValue::S(T::key(key));
-        let partial = self.db
-            .delete_item()
-            .table_name(&self.table_name)
-            .key("pk", key_av)
-            .return_values(ReturnValue::AllOld);
-
-        let partial = match value {
-            Some(value) => partial
-                .condition_expression("content = :value")
-                .expression_attribute_values(
-                    ":value",
-                    AttributeValue::S(serde_json::to_string(&value)?),
-                ),
-            None => partial,
-        };
-
-        let response = partial.send().await?;
-
-        let value = response
-            .attributes()
-            .ok_or(anyhow!("item does not exist"))?
-            .get("content")
-            .unwrap()
-            .as_s()
-            .unwrap();
-
-        Ok(serde_json::from_str::<T::Value>(value)?)
+        let expected = value.map(serde_json::to_string).transpose()?;
+        let value = self.store
+            .delete(&T::key(key), expected)
+            .await?
+            .ok_or(anyhow!("item does not exist"))?;
+
+        Ok(serde_json::from_str::<T::Value>(&value)?)
+    }
+
+    /// Only deletes the value if it hasn't been saved since it was read, see [`Key::version`]
+    pub async fn delete_version<T: Key>(&self, key: &T::Key, version: u64) -> Result<(), Error> {
+        self.store.delete_version(&T::key(key), version).await
     }
 }
@@ -0,0 +1,80 @@
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+
+/// Chosen with the `encoding` query parameter when connecting, JSON if none is given
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, EnumString)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// Same shape as JSON, except hands and team stacks are binary with a byte for each card
+    #[serde(rename = "msgpack")]
+    #[strum(serialize = "msgpack")]
+    MessagePack,
+}
+
+impl Encoding {
+    pub fn from_query(encoding: Option<&str>) -> Option<Self> {
+        encoding.map_or(Some(Encoding::Json), |encoding| encoding.parse().ok())
+    }
+
+    pub fn is_binary(&self) -> bool {
+        *self != Encoding::Json
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(match self {
+            Encoding::Json => serde_json::to_vec(value)?,
+            Encoding::MessagePack => rmp_serde::to_vec_named(value)?,
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(match self {
+            Encoding::Json => serde_json::from_slice(bytes)?,
+            Encoding::MessagePack => rmp_serde::from_slice(bytes)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::encoding::Encoding;
+    use crate::game::testing::spade;
+    use crate::game::Card;
+    use crate::requests::{GameStateData, WebsocketRequest, WebsocketResponse};
+
+    #[test]
+    fn requests_decode_the_same_from_either_encoding() {
+        let request = json!({ "type": "deal", "stack": "stack", "count": 2 });
+        let json = WebsocketRequest::decode(Encoding::Json, &Encoding::Json.encode(&request).unwrap()).unwrap();
+        let msgpack = WebsocketRequest::decode(Encoding::MessagePack, &Encoding::MessagePack.encode(&request).unwrap()).unwrap();
+
+        assert_eq!(format!("{msgpack:?}"), format!("{json:?}"));
+        assert!(WebsocketRequest::decode(Encoding::MessagePack, b"{\"type\":\"ping\"}").is_err());
+    }
+
+    #[test]
+    fn responses_round_trip_through_message_pack() {
+        let response = GameStateData { seq: 3, owner: Some("a".to_string()), ..GameStateData::default() }.with(&"game".to_string());
+        let bytes = Encoding::MessagePack.encode(&response).unwrap();
+
+        let decoded: Value = Encoding::MessagePack.decode(&bytes).unwrap();
+        assert_eq!(decoded, serde_json::to_value(&response).unwrap());
+    }
+
+    #[test]
+    fn message_pack_sends_each_card_in_a_hand_as_a_single_byte() {
+        let face_down = Card::try_from(0b1000_1000).unwrap();
+        let response = WebsocketResponse::PlayerState { game_id: "game".to_string(), hand: vec![spade(1), face_down], card_ids: None };
+
+        let bytes = Encoding::MessagePack.encode(&response).unwrap();
+        // A bin 8 header with a length of two, followed by the cards
+        assert!(bytes.windows(4).any(|window| window == [0xc4, 2, 4, 0b1000_1000]));
+        assert_eq!(serde_json::to_value(&response).unwrap()["hand"], json!([4, 0b1000_1000]));
+    }
+}
@@ -1,34 +1,105 @@
-use std::collections::HashMap;
-use std::iter;
+use std::collections::{HashMap, VecDeque};
+use std::{iter, slice};
+use crate::broadcast::ConnectionId;
 use crate::db_utils::{Key};
-use crate::requests::{DeckType, GameStateData, WebsocketResponse};
+use crate::store::IndexEntry;
+use crate::requests::{DeckType, GameStateData, WebsocketRequest, WebsocketRequestDiscriminants, WebsocketResponse};
 use crate::{Services, WebsocketError};
 use anyhow::{anyhow, Error};
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
 use std::time::{SystemTime, UNIX_EPOCH};
-use rand::rng;
-use rand::seq::SliceRandom;
 use uuid::Uuid;
 
+mod access;
+mod code;
 mod deck;
+mod host;
+mod journal;
+mod kick;
+mod lobby;
+mod log;
 mod player;
+mod seat;
+mod settings;
+mod spectator;
+mod table;
+mod team;
+mod turn;
+#[cfg(test)]
+pub(crate) mod testing;
 
+pub use access::*;
+pub use code::*;
 pub use deck::*;
+pub use journal::*;
+pub use lobby::*;
+pub use log::*;
 pub use player::*;
-use crate::requests::WebsocketRequestDiscriminants::{DropStack, FlipCard, FlipStack, JoinGame, LeaveGame, PopCard, MoveStack, Ping, PutCard, Reset, Shuffle, TakeCard};
+pub use seat::*;
+pub use settings::*;
+pub use team::*;
+use crate::requests::WebsocketRequestDiscriminants::{Deal, DropStack, FlipCard, FlipStack, GivePlayer, JoinGame, LeaveGame, PopCard, MoveStack, Ping, PutCard, ReclaimHand, Reset, Resync, Shuffle, TakeCard};
 
 pub type GameId = String;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Game {
     pub id: GameId,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub public: bool,
     pub created_at: u64, //todo move to separate game data object
     pub owner: PlayerId, // todo move to separate game data object
+    #[serde(default)]
+    pub co_hosts: Vec<PlayerId>,
     pub authorized_players: Vec<PlayerId>, // todo move to separate game data object
+    #[serde(default)]
+    pub access: Access,
     pub deck_type: DeckType,
-    pub connected_players: HashMap<PlayerId, String>,
+    pub connected_players: HashMap<PlayerId, ConnectionId>,
+    #[serde(default)]
+    pub spectators: HashMap<PlayerId, ConnectionId>,
+    #[serde(default)]
+    pub tables: HashMap<PlayerId, ConnectionId>,
+    /// Table devices that can reset, shuffle, deal and move stacks, kept while they're disconnected
+    #[serde(default)]
+    pub table_controls: Vec<PlayerId>,
+    #[serde(default)]
+    pub banned: Vec<PlayerId>,
     pub stacks: Vec<Stack>,
+    #[serde(default)]
+    pub next_stack_id: u64,
+    #[serde(default)]
+    pub settings: GameSettings,
+    #[serde(default)]
+    pub seats: Vec<Seat>,
+    #[serde(default)]
+    pub teams: Vec<Team>,
+    /// Cards are dealt and turns taken in this order
+    #[serde(default)]
+    pub seat_order: Vec<PlayerId>,
+    #[serde(default)]
+    pub turn: Option<PlayerId>,
+    /// When each player that has left, and still has their hand, disconnected
+    #[serde(default)]
+    pub disconnected: HashMap<PlayerId, u64>,
+    #[serde(default)]
+    pub journal: VecDeque<JournalEntry>,
+    /// Actions that have been undone and can be made again, most recently undone last
+    #[serde(default)]
+    pub undone: Vec<JournalEntry>,
+    #[serde(default)]
+    pub seq: u64,
+    #[serde(default)]
+    pub recent_updates: VecDeque<GameStateData>,
+    #[serde(default)]
+    pub version: u64,
+    #[serde(skip)]
+    recorder: Recorder,
     #[serde(skip)]
     _private: PhantomData<()>,
 }
@@ -40,60 +111,126 @@ impl Key for Game {
     fn prefix() -> &'static str {
         "game:game"
     }
+
+    fn version(value: &Self::Value) -> Option<u64> {
+        Some(value.version)
+    }
+
+    fn index(value: &Self::Value) -> Result<Option<IndexEntry>, Error> {
+        value.listing()
+    }
 }
 
+pub const MAX_RECENT_UPDATES: usize = 32;
+
+pub const MAX_SAVE_ATTEMPTS: usize = 5;
+
 // todo periodically scan for stale games and purge from db (TTL?)
 impl Game {
     pub async fn new(
         services: &Services,
         player_id: PlayerId,
+        name: String,
+        public: bool,
         deck_type: DeckType,
+        settings: GameSettings,
+        access: Access,
     ) -> Result<Self, Error> {
         let game_id = Uuid::new_v4().to_string();
+        let mut new_game = Self::create(game_id, now(), player_id, deck_type, settings, Recorder::default())?;
+        new_game.code = allocate_code(services, &new_game.id).await?;
+        new_game.name = name;
+        new_game.public = public;
+        new_game.access = access;
+        if let Err(error) = new_game.save(services).await {
+            new_game.free_code(services).await;
+            return Err(error)
+        }
+        Ok(new_game)
+    }
 
-        let stacks = Stack::from(deck_type.clone());
+    fn create(
+        id: GameId,
+        created_at: u64,
+        owner: PlayerId,
+        deck_type: DeckType,
+        settings: GameSettings,
+        mut recorder: Recorder,
+    ) -> Result<Self, InvalidCardByte> {
+        let mut next_stack_id = 0;
+        let stacks = Stack::from(deck_type.clone(), settings.card_ids, &mut next_stack_id, &mut recorder)?;
+        let teams = settings.teams.iter().map(|id| Team { id: id.clone(), score: 0 }).collect();
 
-        let new_game = Self {
-            id: game_id,
-            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
-            owner: player_id.clone(),
-            authorized_players: vec![player_id],
+        let mut game = Self {
+            id,
+            code: String::new(),
+            name: String::new(),
+            public: false,
+            created_at,
+            owner: owner.clone(),
+            co_hosts: Vec::new(),
+            authorized_players: vec![owner],
+            access: Access::Open,
             connected_players: HashMap::new(),
+            spectators: HashMap::new(),
+            tables: HashMap::new(),
+            table_controls: Vec::new(),
+            banned: Vec::new(),
             deck_type,
             stacks,
+            next_stack_id,
+            settings,
+            seats: Vec::new(),
+            teams,
+            seat_order: Vec::new(),
+            turn: None,
+            disconnected: HashMap::new(),
+            journal: VecDeque::new(),
+            undone: Vec::new(),
+            seq: 0,
+            recent_updates: VecDeque::new(),
+            version: 0,
+            recorder,
             _private: PhantomData,
         };
-
-        services.put::<Game>(&new_game.id, &new_game).await?;
-        Ok(new_game)
+        game.log_created();
+        Ok(game)
     }
 
     pub async fn add_player(
         &mut self,
         services: &Services,
         player_id: PlayerId,
-        conn_id: &str,
+        conn_id: &ConnectionId,
     ) -> Result<Player, Error> {
         let player = match services.get::<Player>(&player_id).await {
-            Some(player) => player,
+            Some(player) if player.game_id == self.id => player,
+            // A hand left in another game isn't brought into this one
+            Some(mut player) => {
+                player.move_to(self.id.clone());
+                player.save(services).await?;
+                player
+            }
             None => Player::new(services, player_id, self.id.clone()).await?,
         };
-        self.connected_players.insert(player.player_id.clone(), conn_id.to_string());
-        services.put::<Game>(&self.id, &self).await?;
-        services.send(conn_id, &GameStateData {
-            cause_action: Some(Ping),
-            // could send current player but won't provide any extra detail and involves another clone
-            cause_player: None,
-            owner: Some(self.owner.clone()),
-            players: Some(self.connected_players.keys().cloned().collect()),
-            stacks: Some(self.stacks.iter().map(Stack::state).collect())
-        }.with(&self.id)).await?;
-        self.send_state_all(services, &GameStateData {
+        self.connected_players.insert(player.player_id.clone(), conn_id.clone());
+        // Rejoining stops their hand from being reclaimed
+        self.disconnected.remove(&player.player_id);
+        self.seat(&player.player_id);
+        let update = self.record(GameStateData {
             cause_action: Some(JoinGame),
             cause_player: Some(player.player_id.clone()),
+            seats: Some(self.seats.clone()),
+            seat_order: Some(self.seat_order.clone()),
+            turn: self.turn.clone(),
             ..Default::default()
-        }.with(&self.id)).await?;
+        });
+        self.save(services).await?;
+        // Send the full state first, so the new player can ignore any update it already includes
+        services.send(conn_id, &self.snapshot(Ping).with(&self.id)).await?;
+        self.send_state_all(services, &update).await?;
         player.send_state(services, conn_id).await?;
+        self.send_team_stacks_to(services, &player.player_id, conn_id).await?;
         Ok(player)
     }
 
@@ -102,8 +239,11 @@ impl Game {
         services: &Services,
         player_id: PlayerId
     ) -> Result<(), Error> {
+        if self.authorized_players.contains(&player_id) {
+            return Ok(())
+        }
         self.authorized_players.push(player_id);
-        services.put::<Game>(&self.id, &self).await?;
+        self.save(services).await?;
         Ok(())
     }
 
@@ -112,39 +252,50 @@ impl Game {
         services: &Services,
         player_id: PlayerId,
     ) -> Result<(), Error> {
-        if let None = self.connected_players.remove(&player_id) {
+        if self.connected_players.remove(&player_id).is_none() {
             return Err(anyhow!("player not in this game"))
         }
-        // Keep player state in database incase they join back
+        // Keep player state in database incase they join back, until their hand is reclaimed.
+        // Their seat is kept for them until then too
+        self.disconnected.insert(player_id.clone(), now());
+        self.update_seat_order();
 
         if self.connected_players.is_empty() {
             self.destroy(services).await?;
             return Ok(())
         }
 
+        let mut co_hosts = None;
         if self.owner == player_id {
             // Game owner has left, assign new owner
             // can safely call unwrap as we know the list is not empty
-            self.owner = self.connected_players.keys().next().unwrap().clone();
+            self.owner = self.successor().unwrap();
+            let owner = &self.owner;
+            self.co_hosts.retain(|co_host| co_host != owner);
+            co_hosts = Some(self.co_hosts.clone());
         }
-        services.put::<Game>(&self.id, &self).await?;
-        self.send_state_all(services, &GameStateData {
+        self.commit(services, [], GameStateData {
             cause_action: Some(LeaveGame),
             cause_player: Some(player_id),
             owner: Some(self.owner.clone()),
+            co_hosts,
+            seat_order: Some(self.seat_order.clone()),
+            turn: self.turn.clone(),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         Ok(())
     }
 
+    /// Fails with a conflict, before anything is removed, if the game has been saved since it was read
     pub async fn destroy(self, services: &Services) -> Result<(), Error> {
-        services.delete::<Game>(&self.id, None).await?;
+        services.delete_version::<Game>(&self.id, self.version).await?;
+        self.free_code(services).await;
         for player in &self.authorized_players {
-            services.delete::<Player>(&player, None).await?;
+            self.delete_player(services, player).await?;
         }
         self.send_state_all(services, &WebsocketResponse::CloseGame).await?;
-        for (_, conn_id) in self.connected_players.into_iter() {
-            let _ = services.delete_connection(&conn_id).await;
+        for (_, conn_id) in self.connected_players.into_iter().chain(self.spectators).chain(self.tables) {
+            let _ = services.delete_connection(&conn_id.id).await;
         }
         Ok(())
     }
@@ -154,32 +305,110 @@ impl Game {
             .iter_mut()
             // todo check cost of deref
             .find(|s| s.id == *stack_id)
-            .ok_or_else(|| WebsocketError::StackNotFound)
+            .ok_or(WebsocketError::StackNotFound)
     }
 
-    /// Returns the popped card, as well as the remaining stack state
-    fn pop_from_stack(&mut self, stack_id: StackId) -> Result<(Card, StackState), WebsocketError> {
+    fn pop_from_stack(&mut self, stack_id: StackId) -> Result<(Card, Option<CardId>, StackState), WebsocketError> {
         let stack_index = self.stacks.iter().position(|s| s.id == stack_id)
             .ok_or(WebsocketError::StackNotFound)?;
 
         let stack = self.stacks.get_mut(stack_index).unwrap();
-        let card = stack.cards.pop().ok_or(WebsocketError::EmptyStack)?;
+        let (card, id) = stack.pop().ok_or(WebsocketError::EmptyStack)?;
         let state = stack.state();
 
         if stack.cards.is_empty() {
             self.stacks.swap_remove(stack_index);
         }
-        Ok((card, state))
+        Ok((card, id, state))
     }
 
     async fn get_player(&self, services: &Services, player_id: &PlayerId) -> Result<Player, WebsocketError> {
         services.get::<Player>(player_id)
             .await
-            .ok_or_else(|| WebsocketError::PlayerNotFound)
+            .ok_or(WebsocketError::PlayerNotFound)
+    }
+
+    /// Deletes the player's hand if it's in this game, as they may have since joined another
+    async fn delete_player(&self, services: &Services, player_id: &PlayerId) -> Result<(), Error> {
+        if let Some(player) = services.get::<Player>(player_id).await.filter(|player| player.game_id == self.id) {
+            services.delete::<Player>(player_id, Some(&player)).await?;
+        }
+        Ok(())
+    }
+
+    async fn save(&mut self, services: &Services) -> Result<(), Error> {
+        self.save_with(services, []).await
+    }
+
+    /// Stores the game and players in a single transaction
+    async fn save_with<'a>(
+        &mut self,
+        services: &Services,
+        players: impl IntoIterator<Item = &'a mut Player>,
+    ) -> Result<(), Error> {
+        self.version += 1;
+        let mut items = vec![Game::item(&self.id, self)?];
+        for player in players {
+            player.version += 1;
+            items.push(Player::item(&player.player_id, player)?);
+        }
+        let appends = self.take_log()?;
+        services.put_all(items, appends).await
+    }
+
+    /// The returned response should only be sent once the game has been saved
+    fn record(&mut self, mut data: GameStateData) -> WebsocketResponse {
+        self.seq += 1;
+        data.seq = self.seq;
+        self.log_update(&data);
+        if self.recent_updates.len() == MAX_RECENT_UPDATES {
+            self.recent_updates.pop_front();
+        }
+        self.recent_updates.push_back(data.clone());
+        data.with(&self.id)
+    }
+
+    /// Records, saves and sends the update. Team members are also sent their changed team stacks
+    async fn commit<'a>(
+        &mut self,
+        services: &Services,
+        players: impl IntoIterator<Item = &'a mut Player>,
+        data: GameStateData,
+    ) -> Result<(), Error> {
+        let team_stacks: Vec<(StackId, TeamId)> = data.stacks.iter()
+            .flatten()
+            .filter_map(|s| s.team.as_ref().map(|team| (s.stack_id.clone(), team.clone())))
+            .collect();
+        let update = self.record(data);
+        self.save_with(services, players).await?;
+        self.send_state_all(services, &update).await?;
+        self.send_team_stacks(services, &team_stacks).await
     }
 
-    async fn save(&self, services: &Services) -> Result<(), Error> {
-        services.put::<Game>(&self.id, self).await.map(|_| ())
+    fn snapshot(&self, cause_action: WebsocketRequestDiscriminants) -> GameStateData {
+        GameStateData {
+            seq: self.seq,
+            snapshot: Some(true),
+            cause_action: Some(cause_action),
+            // could send current player but won't provide any extra detail and involves another clone
+            cause_player: None,
+            code: (!self.code.is_empty()).then(|| self.code.clone()),
+            name: Some(self.name.clone()),
+            owner: Some(self.owner.clone()),
+            co_hosts: Some(self.co_hosts.clone()),
+            players: Some(self.connected_players.keys().cloned().collect()),
+            spectators: Some(self.spectator_list()),
+            tables: Some(self.table_list()),
+            table_controls: Some(self.table_controls.clone()),
+            stacks: Some(self.stacks.iter().map(Stack::state).collect()),
+            seats: Some(self.seats.clone()),
+            teams: Some(self.teams.clone()),
+            seat_order: Some(self.seat_order.clone()),
+            turn: self.turn.clone(),
+            turn_policy: Some(self.settings.turn_policy),
+            max_players: self.settings.max_players,
+            max_spectators: self.settings.max_spectators,
+        }
     }
 
     fn stack_at_position(&mut self, position: Position, create_if_none: bool) -> Option<&mut Stack> {
@@ -191,16 +420,13 @@ impl Game {
             return None;
         }
 
-        self.stacks.push(Stack {
-            id: Uuid::new_v4().to_string(),
-            cards: Vec::new(),
-            position,
-        });
+        self.stacks.push(Stack::empty(new_stack_id(&mut self.next_stack_id), position));
 
         self.stacks.last_mut()
     }
 
-    pub async fn flip_card(&mut self, services: &Services, stack_id: StackId) -> Result<(), WebsocketError> {
+    pub async fn flip_card(&mut self, services: &Services, stack_id: StackId, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        let before = self.capture(slice::from_ref(&stack_id));
         let state = {
             let stack = self.get_stack(stack_id)?;
         if stack.cards.is_empty() {
@@ -210,57 +436,65 @@ impl Game {
         stack.cards.last_mut().unwrap().flip();
             stack.state()
             };
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        self.journal(player_id, FlipCard, before, &[], None);
+        self.commit(services, [], GameStateData {
             cause_action: Some(FlipCard),
             stacks: Some(vec![state]),
             .. Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         Ok(())
     }
 
-    pub async fn flip_stack(&mut self, services: &Services, stack_id: StackId) -> Result<(), WebsocketError> {
+    pub async fn flip_stack(&mut self, services: &Services, stack_id: StackId, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        let before = self.capture(slice::from_ref(&stack_id));
         let state = {
             let stack = self.get_stack(stack_id)?;
-            stack.cards.reverse();
+            stack.reverse();
             for card in &mut stack.cards {
                 card.flip();
             }
             stack.state()
         };
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        self.journal(player_id, FlipStack, before, &[], None);
+        self.commit(services, [], GameStateData {
             cause_action: Some(FlipStack),
             stacks: Some(vec![state]),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         Ok(())
     }
 
-    pub async fn shuffle_stack(&mut self, services: &Services, stack_id: StackId) -> Result<(), WebsocketError> {
+    pub async fn shuffle_stack(&mut self, services: &Services, stack_id: StackId, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        let before = self.capture(slice::from_ref(&stack_id));
         let state = {
-            let stack = self.get_stack(stack_id)?;
-            stack.cards.shuffle(&mut rng());
+            let stack = self.stacks.iter_mut()
+                .find(|s| s.id == stack_id)
+                .ok_or(WebsocketError::StackNotFound)?;
+            stack.shuffle(&mut self.recorder);
             stack.state()
         };
+        self.journal(player_id, Shuffle, before, &[], None);
 
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        self.commit(services, [], GameStateData {
             cause_action: Some(Shuffle),
             stacks: Some(vec![state]),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         Ok(())
     }
 
-    pub async fn drop_stack(&mut self, services: &Services, stack_id: StackId, position: Position) -> Result<(), WebsocketError> {
+    pub async fn drop_stack(&mut self, services: &Services, stack_id: StackId, position: Position, player_id: &PlayerId) -> Result<(), WebsocketError> {
         let stack_index = self.stacks.iter().position(|s| s.id == stack_id)
             .ok_or(WebsocketError::StackNotFound)?;
+        let target_id = self.stacks.iter()
+            .find(|s| s.position == position && s.id != stack_id)
+            .map(|s| s.id.clone());
+        let before = self.capture(&iter::once(stack_id).chain(target_id).collect::<Vec<_>>());
 
         let (old_stack_state, new_stack_state) = {
             let mut mut_stack = self.stacks.swap_remove(stack_index);
             if let Some(target_stack) = self.stack_at_position(position, false) {
-                target_stack.cards.extend(mut_stack.cards.drain(..));
+                target_stack.append(&mut mut_stack.cards, &mut mut_stack.card_ids);
                 (Some(mut_stack.state()), target_stack.state())
             } else {
                 mut_stack.position = position;
@@ -270,64 +504,68 @@ impl Game {
             }
         };
 
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        self.journal(player_id, DropStack, before, &[], None);
+        self.commit(services, [], GameStateData {
             cause_action: Some(DropStack),
             stacks: Some(old_stack_state.into_iter().chain(iter::once(new_stack_state)).collect()),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         Ok(())
     }
 
     // todo stack order is guaranteed in database, so players joining a game in progress will have incorrectly ordered stacks
-    pub async fn pop_card(&mut self, services: &Services, stack_id: StackId) -> Result<(), WebsocketError> {
-        let (card, old_stack_state) = self.pop_from_stack(stack_id)?;
-        let target_stack = Stack {
-            id: Uuid::new_v4().to_string(),
-            cards: vec![card],
-            position: old_stack_state.position,
-        };
+    pub async fn pop_card(&mut self, services: &Services, stack_id: StackId, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        let before = self.capture(slice::from_ref(&stack_id));
+        let (card, id, old_stack_state) = self.pop_from_stack(stack_id)?;
+        let mut target_stack = Stack::empty(new_stack_id(&mut self.next_stack_id), old_stack_state.position);
+        target_stack.push(card, id);
         let new_stack_state = target_stack.state();
+        let new_stack_id = target_stack.id.clone();
         self.stacks.push(target_stack);
 
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        self.journal(player_id, PopCard, before, &[new_stack_id], None);
+
+        self.commit(services, [], GameStateData {
             cause_action: Some(PopCard),
             stacks: Some(vec![old_stack_state, new_stack_state]),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         Ok(())
     }
 
-    pub async fn move_stack(&mut self, services: &Services, stack_id: StackId, position: Position) -> Result<(), WebsocketError> {
+    pub async fn move_stack(&mut self, services: &Services, stack_id: StackId, position: Position, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        let before = self.capture(slice::from_ref(&stack_id));
         let new_stack_state = {
             let stack = self.get_stack(stack_id)?;
             stack.position = position;
             stack.state()
         };
 
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        self.journal(player_id, MoveStack, before, &[], None);
+
+        self.commit(services, [], GameStateData {
             cause_action: Some(MoveStack),
             stacks: Some(vec![new_stack_state]),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         Ok(())
     }
 
-    pub async fn take_card(&mut self, services: &Services, stack_id: StackId, player_id: &PlayerId, conn_id: &str) -> Result<(), WebsocketError> {
+    pub async fn take_card(&mut self, services: &Services, stack_id: StackId, player_id: &PlayerId, conn_id: &ConnectionId) -> Result<(), WebsocketError> {
         let mut player = self.get_player(services, player_id).await?;
-        let (card, stack_state) = self.pop_from_stack(stack_id)?;
-        player.hand.push(card);
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        let hand_before = (player.hand.clone(), player.hand_ids.clone());
+        let before = self.capture(slice::from_ref(&stack_id));
+        let (card, id, stack_state) = self.pop_from_stack(stack_id)?;
+        player.give(card, id);
+        let hand_after = (player.hand.clone(), player.hand_ids.clone());
+        self.journal(player_id, TakeCard, before, &[], Some((hand_before, hand_after)));
+        self.commit(services, [&mut player], GameStateData {
             cause_action: Some(TakeCard),
             cause_player: Some(player_id.clone()),
             stacks: Some(vec![stack_state]),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
         player.send_state(services, conn_id).await?;
-        services.put::<Player>(&player.player_id, &player).await?;
         Ok(())
     }
 
@@ -338,58 +576,497 @@ impl Game {
         hand_index: usize,
         position: Position,
         face_down: bool,
-        conn_id: &str
+        conn_id: &ConnectionId
     ) -> Result<(), WebsocketError> {
         let mut player = self.get_player(services, player_id).await?;
-        if hand_index >= player.hand.len() {
-            return Err(WebsocketError::CardNotFound)
-        }
+        let hand_before = (player.hand.clone(), player.hand_ids.clone());
+        let (mut card, id) = player.take(hand_index).ok_or(WebsocketError::CardNotFound)?;
+        let existing_id = self.stacks.iter()
+            .find(|s| s.position == position)
+            .map(|s| s.id.clone());
+        let before = self.capture(&Vec::from_iter(existing_id));
 
-        let state = {
+        let (state, target_id) = {
             let target_stack = self.stack_at_position(position, true).unwrap();
-            let mut card = player.hand.swap_remove(hand_index);
             if face_down != card.is_face_down() {
                 card.flip()
             }
-            target_stack.cards.push(card);
-            target_stack.state()
+            target_stack.push(card, id);
+            (target_stack.state(), target_stack.id.clone())
         };
+        let hand_after = (player.hand.clone(), player.hand_ids.clone());
+        self.journal(player_id, PutCard, before, &[target_id], Some((hand_before, hand_after)));
 
-        player.send_state(services, conn_id).await?;
-        services.put::<Player>(&player.player_id, &player).await?;
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData{
+        self.commit(services, [&mut player], GameStateData {
             cause_action: Some(PutCard),
             cause_player: Some(player_id.clone()),
             stacks: Some(vec![state]),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
+        player.send_state(services, conn_id).await?;
         Ok(())
     }
 
-    pub async fn reset(&mut self, services: &Services) -> Result<(), WebsocketError> {
-        for player_id in &self.authorized_players {
-            if let Some(conn_id) = self.connected_players.get(player_id) {
-                let mut player = self.get_player(services, &player_id).await?;
-                player.hand = Vec::new();
+    /// Starts from `starting_player`, or the player after the dealer if not given
+    pub async fn deal(
+        &mut self,
+        services: &Services,
+        stack_id: StackId,
+        dealer: &PlayerId,
+        count: Option<usize>,
+        starting_player: Option<PlayerId>,
+    ) -> Result<(), WebsocketError> {
+        // Deal in seat order so every client sees a consistent order
+        let mut order = self.seat_order.clone();
+        if order.is_empty() {
+            return Err(WebsocketError::InvalidRequest("no seated players"))
+        }
+        let start = match starting_player {
+            Some(player_id) => order.iter()
+                .position(|p| *p == player_id)
+                .ok_or(WebsocketError::PlayerNotFound)?,
+            None => order.iter()
+                .position(|p| p == dealer)
+                .map_or(0, |i| (i + 1) % order.len()),
+        };
+        order.rotate_left(start);
+
+        let stack_index = self.stacks.iter().position(|s| s.id == stack_id)
+            .ok_or(WebsocketError::StackNotFound)?;
+        let available = self.stacks[stack_index].cards.len();
+        let total = match count {
+            Some(count) => count.checked_mul(order.len())
+                .filter(|total| *total <= available)
+                .ok_or(WebsocketError::InvalidRequest("not enough cards in stack to deal"))?,
+            None => available,
+        };
+
+        let mut players = Vec::with_capacity(order.len());
+        for player_id in &order {
+            players.push(self.get_player(services, player_id).await?);
+        }
+
+        let state = {
+            let stack = &mut self.stacks[stack_index];
+            for i in 0..total {
+                // can safely call unwrap as we know there are enough cards in the stack
+                let (card, id) = stack.pop().unwrap();
+                players[i % order.len()].give(card, id);
+            }
+            stack.state()
+        };
+        if self.stacks[stack_index].cards.is_empty() {
+            self.stacks.swap_remove(stack_index);
+        }
+
+        self.clear_journal();
+        self.commit(services, &mut players, GameStateData {
+            cause_action: Some(Deal),
+            cause_player: Some(dealer.clone()),
+            stacks: Some(vec![state]),
+            ..Default::default()
+        }).await?;
+        for player in &players {
+            if let Some(conn_id) = self.connected_players.get(&player.player_id) {
                 player.send_state(services, conn_id).await?;
-                services.put::<Player>(&player_id, &player).await?;
-            } else {
-                services.delete::<Player>(&player_id, None).await?;
             }
         }
-        self.stacks = Stack::from(self.deck_type.clone());
-        self.save(services).await?;
-        self.send_state_all(services, &GameStateData {
+        Ok(())
+    }
+
+    pub async fn give_player(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        hand_index: usize,
+        trade_to: PlayerId,
+        conn_id: &ConnectionId,
+    ) -> Result<(), WebsocketError> {
+        if *player_id == trade_to {
+            return Err(WebsocketError::InvalidRequest("cannot give a card to yourself"))
+        }
+        if !self.authorized_players.contains(&trade_to) {
+            return Err(WebsocketError::PlayerNotFound)
+        }
+
+        let mut player = self.get_player(services, player_id).await?;
+        if hand_index >= player.hand.len() {
+            return Err(WebsocketError::CardNotFound)
+        }
+        // Players who haven't connected yet won't have a hand stored
+        let mut recipient = match services.get::<Player>(&trade_to).await {
+            Some(recipient) if recipient.game_id == self.id => recipient,
+            Some(_) => return Err(WebsocketError::PlayerNotFound),
+            None => Player::empty(trade_to.clone(), self.id.clone()),
+        };
+
+        let (card, id) = player.take(hand_index).ok_or(WebsocketError::CardNotFound)?;
+        recipient.give(card, id);
+        self.clear_journal();
+        self.commit(services, [&mut player, &mut recipient], GameStateData {
+            cause_action: Some(GivePlayer),
+            cause_player: Some(player_id.clone()),
+            ..Default::default()
+        }).await?;
+
+        player.send_state(services, conn_id).await?;
+        if let Some(recipient_conn) = self.connected_players.get(&trade_to) {
+            recipient.send_state(services, recipient_conn).await?;
+        }
+        Ok(())
+    }
+
+    /// Falls back to a snapshot if some of the updates after `since` are no longer kept
+    pub async fn resync(&self, services: &Services, player_id: &PlayerId, since: u64, conn_id: &ConnectionId) -> Result<(), WebsocketError> {
+        let oldest_kept = self.recent_updates.front().map_or(self.seq + 1, |update| update.seq);
+        if since <= self.seq && since + 1 >= oldest_kept {
+            for update in self.recent_updates.iter().filter(|update| update.seq > since) {
+                services.send(conn_id, &update.clone().with(&self.id)).await?;
+            }
+        } else {
+            services.send(conn_id, &self.snapshot(Resync).with(&self.id)).await?;
+        }
+
+        if !self.spectators.contains_key(player_id) && !self.tables.contains_key(player_id) {
+            self.get_player(services, player_id).await?
+                .send_state(services, conn_id)
+                .await?;
+            self.send_team_stacks_to(services, player_id, conn_id).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn reclaim_expired(&mut self, services: &Services) -> Result<(), WebsocketError> {
+        let Some(reclaim_after) = self.settings.reclaim_after else {
+            return Ok(())
+        };
+        // Reclaiming is logged as its own request, which is replayed instead
+        if self.recorder.is_replaying() {
+            return Ok(())
+        }
+        let expired: Vec<PlayerId> = self.disconnected.iter()
+            .filter(|(_, disconnected_at)| now() >= *disconnected_at + reclaim_after)
+            .map(|(player_id, _)| player_id.clone())
+            .collect();
+
+        for player_id in expired {
+            self.handling(None, &WebsocketRequest::ReclaimHand { player: player_id.clone() });
+            self.reclaim_hand(services, player_id).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn reclaim_hand(&mut self, services: &Services, player_id: PlayerId) -> Result<(), WebsocketError> {
+        if !self.authorized_players.contains(&player_id) {
+            return Err(WebsocketError::PlayerNotFound)
+        }
+        if self.connected_players.contains_key(&player_id) {
+            return Err(WebsocketError::InvalidRequest("cannot reclaim the hand of a connected player"))
+        }
+        self.disconnected.remove(&player_id);
+        let seats = self.free_seat(&player_id).then(|| self.seats.clone());
+
+        let mut player = match services.get::<Player>(&player_id).await {
+            // The player may have since joined another game
+            Some(player) if player.game_id == self.id && !player.hand.is_empty() => player,
+            // Nothing to return to the table, but the player is no longer waited for and others may
+            // need to know the seat is free
+            _ => {
+                self.commit(services, [], GameStateData {
+                    cause_action: Some(ReclaimHand),
+                    cause_player: Some(player_id),
+                    seats,
+                    ..Default::default()
+                }).await?;
+                return Ok(())
+            }
+        };
+
+        let state = self.return_hand(&mut player);
+        self.clear_journal();
+        self.commit(services, [&mut player], GameStateData {
+            cause_action: Some(ReclaimHand),
+            cause_player: Some(player_id),
+            stacks: Some(vec![state]),
+            seats,
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+
+    fn return_hand(&mut self, player: &mut Player) -> StackState {
+        let (mut cards, mut ids) = player.take_hand();
+        for card in &mut cards {
+            if !card.is_face_down() {
+                card.flip();
+            }
+        }
+        let position = self.settings.reclaim_position.unwrap_or_else(|| self.free_position());
+        let target_stack = self.stack_at_position(position, true).unwrap();
+        target_stack.append(&mut cards, &mut ids);
+        target_stack.state()
+    }
+
+    fn free_position(&self) -> Position {
+        (0..)
+            .map(Stack::grid_position)
+            .find(|position| self.stacks.iter().all(|s| s.position != *position))
+            .unwrap()
+    }
+
+    pub async fn reset(&mut self, services: &Services) -> Result<(), WebsocketError> {
+        let mut players = Vec::new();
+        for player_id in self.connected_players.keys() {
+            let mut player = self.get_player(services, player_id).await?;
+            player.take_hand();
+            players.push(player);
+        }
+        self.stacks = Stack::from(
+            self.deck_type.clone(),
+            self.settings.card_ids,
+            &mut self.next_stack_id,
+            &mut self.recorder,
+        ).map_err(|error| WebsocketError::ServiceError(error.to_string()))?;
+        self.clear_journal();
+        self.commit(services, &mut players, GameStateData {
             cause_action: Some(Reset),
             stacks: Some(self.stacks.iter().map(Stack::state).collect()),
             ..Default::default()
-        }.with(&self.id)).await?;
+        }).await?;
+
+        for player_id in &self.authorized_players {
+            if !self.connected_players.contains_key(player_id) {
+                let _ = self.delete_player(services, player_id).await;
+            }
+        }
+        for player in &players {
+            player.send_state(services, &self.connected_players[&player.player_id]).await?;
+        }
         Ok(())
     }
 
-    /// Send a websocket response to all players connected to the game
     async fn send_state_all(&self, services: &Services, data: &WebsocketResponse) -> Result<(), Error> {
-        services.send_batch(self.connected_players.values(), data).await
+        let connections = self.connected_players.values()
+            .chain(self.spectators.values())
+            .chain(self.tables.values());
+        services.send_batch(connections, data).await
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Role;
+    use crate::game::testing::{conn_id, spade, spades, TestGame};
+    use crate::game::{Access, Game, GameSettings, MAX_RECENT_UPDATES};
+    use crate::handlers::websocket::handle_message;
+    use crate::requests::{GameStateData, WebsocketRequest, WebsocketResponse};
+    use crate::WebsocketError;
+
+    async fn deal(test: &TestGame, dealer: &str, count: Option<usize>, starting_player: Option<&str>) -> Result<(), WebsocketError> {
+        let stack = test.game().await.stacks[0].id.clone();
+        let starting_player = starting_player.map(str::to_string);
+        test.send(dealer, WebsocketRequest::Deal { stack, count, starting_player }).await
+    }
+
+    async fn give(test: &TestGame, player: &str, hand_index: usize, trade_to: &str) -> Result<(), WebsocketError> {
+        test.send(player, WebsocketRequest::GivePlayer { hand_index, trade_to: trade_to.to_string() }).await
+    }
+
+    async fn flip_top_card(test: &TestGame, player: &str) {
+        let stack = test.game().await.stacks[0].id.clone();
+        test.send(player, WebsocketRequest::FlipCard { stack }).await.unwrap();
+    }
+
+    fn updates(responses: Vec<WebsocketResponse>) -> Vec<GameStateData> {
+        responses.into_iter().filter_map(|response| match response {
+            WebsocketResponse::GameState { data, .. } => Some(*data),
+            _ => None,
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn deal_starts_after_the_dealer() {
+        let test = TestGame::new(spades(7), GameSettings::default(), &["a", "b", "c"]).await;
+        deal(&test, "a", Some(2), None).await.unwrap();
+
+        assert_eq!(test.hand("b").await, [spade(7), spade(4)]);
+        assert_eq!(test.hand("c").await, [spade(6), spade(3)]);
+        assert_eq!(test.hand("a").await, [spade(5), spade(2)]);
+        assert_eq!(test.game().await.stacks[0].cards, [spade(1)]);
+    }
+
+    #[tokio::test]
+    async fn dealing_the_whole_stack_gives_the_remainder_to_the_first_players() {
+        let test = TestGame::new(spades(5), GameSettings::default(), &["a", "b"]).await;
+        deal(&test, "b", None, Some("a")).await.unwrap();
+
+        assert_eq!(test.hand("a").await, [spade(5), spade(3), spade(1)]);
+        assert_eq!(test.hand("b").await, [spade(4), spade(2)]);
+        assert!(test.game().await.stacks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn deal_rejects_more_cards_than_the_stack_has() {
+        let test = TestGame::new(spades(5), GameSettings::default(), &["a", "b"]).await;
+
+        for count in [3, usize::MAX] {
+            let result = deal(&test, "a", Some(count), None).await;
+            assert!(matches!(result, Err(WebsocketError::InvalidRequest("not enough cards in stack to deal"))));
+        }
+        assert!(test.hand("a").await.is_empty());
+        assert_eq!(test.game().await.stacks[0].cards.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn deal_rejects_a_table_with_no_seated_players() {
+        let test = TestGame::new(spades(5), GameSettings::default(), &["a"]).await;
+        test.send("a", WebsocketRequest::LeaveSeat).await.unwrap();
+
+        let result = deal(&test, "a", Some(1), None).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("no seated players"))));
+    }
+
+    #[tokio::test]
+    async fn saving_a_stale_game_is_a_conflict() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        let mut stale = test.game().await;
+        deal(&test, "a", Some(1), None).await.unwrap();
+
+        let stack = stale.stacks[0].id.clone();
+        let result = stale.deal(&test.services, stack, &"a".to_string(), Some(1), None).await;
+        assert!(matches!(result, Err(WebsocketError::Conflict)));
+        assert_eq!(test.hand("a").await, [spade(3)]);
+        assert_eq!(test.game().await.stacks[0].cards, [spade(1), spade(2)]);
+    }
+
+    #[tokio::test]
+    async fn ending_a_stale_game_is_a_conflict() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a"]).await;
+        let stale = test.game().await;
+        let seq = stale.seq;
+        flip_top_card(&test, "a").await;
+
+        let result = stale.destroy(&test.services).await.map_err(WebsocketError::from);
+        assert!(matches!(result, Err(WebsocketError::Conflict)));
+        assert_eq!(test.game().await.seq, seq + 1);
+    }
+
+    #[tokio::test]
+    async fn games_saved_before_connections_had_an_encoding_can_be_read() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a"]).await;
+        let mut value = serde_json::to_value(test.game().await).unwrap();
+        value["connected_players"] = serde_json::json!({ "a": "a" });
+
+        let game: Game = serde_json::from_value(value).unwrap();
+        assert_eq!(game.connected_players["a"], conn_id("a"));
+    }
+
+    #[tokio::test]
+    async fn resync_resends_the_missed_updates() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        let since = test.game().await.seq;
+        flip_top_card(&test, "b").await;
+        flip_top_card(&test, "b").await;
+        test.responses("a");
+
+        test.send("a", WebsocketRequest::Resync { since }).await.unwrap();
+
+        let resent = updates(test.responses("a"));
+        assert_eq!(resent.iter().map(|update| update.seq).collect::<Vec<_>>(), [since + 1, since + 2]);
+        assert!(resent.iter().all(|update| update.snapshot.is_none()));
+    }
+
+    #[tokio::test]
+    async fn resync_sends_a_snapshot_once_missed_updates_are_dropped() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        let since = test.game().await.seq;
+        for _ in 0..=MAX_RECENT_UPDATES {
+            flip_top_card(&test, "b").await;
+        }
+        test.responses("a");
+
+        test.send("a", WebsocketRequest::Resync { since }).await.unwrap();
+
+        let resent = updates(test.responses("a"));
+        assert_eq!(resent.len(), 1);
+        assert_eq!(resent[0].snapshot, Some(true));
+        assert_eq!(resent[0].seq, test.game().await.seq);
+        assert_eq!(resent[0].stacks, Some(test.game().await.stacks.iter().map(|stack| stack.state()).collect()));
+    }
+
+    #[tokio::test]
+    async fn card_ids_follow_cards_into_hands_and_back() {
+        let settings = GameSettings { card_ids: true, ..Default::default() };
+        let test = TestGame::new(spades(3), settings, &["a", "b"]).await;
+        let game = test.game().await;
+        let (stack, ids) = (game.stacks[0].id.clone(), game.stacks[0].card_ids.clone());
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(sorted, [0, 1, 2]);
+
+        test.send("a", WebsocketRequest::TakeCard { stack }).await.unwrap();
+        assert_eq!(test.player("a").await.unwrap().hand_ids, [ids[2]]);
+        assert_eq!(test.game().await.stacks[0].card_ids, ids[..2]);
+
+        test.send("a", WebsocketRequest::PutCard { hand_index: 0, position: (0, 0), face_down: false }).await.unwrap();
+        assert!(test.player("a").await.unwrap().hand_ids.is_empty());
+        let game = test.game().await;
+        let put = game.stacks.iter().find(|stack| stack.position == (0, 0)).unwrap();
+        assert_eq!(put.card_ids, [ids[2]]);
+        assert_eq!(put.state().card_ids, Some(vec![ids[2]]));
+    }
+
+    #[tokio::test]
+    async fn give_player_moves_the_card_into_the_recipients_hand() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        deal(&test, "b", None, None).await.unwrap();
+        test.responses("b");
+
+        give(&test, "a", 0, "b").await.unwrap();
+
+        assert_eq!(test.hand("b").await, [spade(3), spade(1), spade(4)]);
+        let sent_hand = test.responses("b").into_iter().find_map(|response| match response {
+            WebsocketResponse::PlayerState { hand, .. } => Some(hand),
+            _ => None,
+        });
+        assert_eq!(sent_hand.unwrap(), [spade(3), spade(1), spade(4)]);
+    }
+
+    #[tokio::test]
+    async fn give_player_rejects_players_outside_the_game() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        deal(&test, "b", None, None).await.unwrap();
+
+        assert!(matches!(give(&test, "a", 0, "stranger").await, Err(WebsocketError::PlayerNotFound)));
+        assert!(matches!(give(&test, "a", 0, "a").await, Err(WebsocketError::InvalidRequest(_))));
+        assert!(matches!(give(&test, "a", 2, "b").await, Err(WebsocketError::CardNotFound)));
+        assert_eq!(test.hand("a").await, [spade(4), spade(2)]);
+    }
+
+    #[tokio::test]
+    async fn players_leave_their_hand_behind_when_they_join_another_game() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        deal(&test, "a", None, None).await.unwrap();
+        test.send("b", WebsocketRequest::LeaveGame).await.unwrap();
+
+        let other = Game::new(&test.services, "b".to_string(), "other".to_string(), false, spades(1), GameSettings::default(), Access::Open)
+            .await
+            .unwrap();
+        handle_message(&test.services, WebsocketRequest::JoinGame, "b".to_string(), other.id.clone(), Role::Player, &conn_id("b"))
+            .await
+            .unwrap();
+        let player = test.player("b").await.unwrap();
+        assert_eq!(player.game_id, other.id);
+        assert!(player.hand.is_empty());
+
+        // The first game can still free their seat, and ending it leaves their new hand alone
+        test.send("a", WebsocketRequest::ReclaimHand { player: "b".to_string() }).await.unwrap();
+        assert!(!test.game().await.seat_order.contains(&"b".to_string()));
+        test.send("a", WebsocketRequest::LeaveGame).await.unwrap();
+        assert_eq!(test.player("b").await.unwrap().game_id, other.id);
     }
 }
@@ -0,0 +1,194 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::auth::Role;
+use crate::broadcast::ConnectionId;
+use crate::game::{Game, PlayerId};
+use crate::requests::WebsocketResponse;
+use crate::{Services, WebsocketError};
+
+pub const MAX_INVITES: usize = 50;
+
+/// Players who have joined before can always come back
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Access {
+    #[default]
+    Open,
+    Passphrase { hash: String },
+    /// Invites can each be used once
+    InviteOnly { invites: Vec<String> },
+}
+
+impl Access {
+    /// A game can have a passphrase or be invite only, but not both
+    pub fn new(passphrase: Option<String>, invite_only: bool) -> Result<Self, WebsocketError> {
+        match (passphrase, invite_only) {
+            (Some(_), true) => Err(WebsocketError::InvalidRequest("a game cannot have a passphrase and be invite only")),
+            (Some(passphrase), false) if passphrase.is_empty() => {
+                Err(WebsocketError::InvalidRequest("the passphrase cannot be empty"))
+            }
+            (Some(passphrase), false) => {
+                let salt = SaltString::generate(&mut OsRng);
+                let hash = Argon2::default()
+                    .hash_password(passphrase.as_bytes(), &salt)
+                    .map_err(|error| WebsocketError::ServiceError(error.to_string()))?;
+                Ok(Access::Passphrase { hash: hash.to_string() })
+            }
+            (None, true) => Ok(Access::InviteOnly { invites: Vec::new() }),
+            (None, false) => Ok(Access::Open),
+        }
+    }
+
+    fn check(&mut self, passphrase: Option<&str>, invite: Option<&str>) -> Result<bool, WebsocketError> {
+        match self {
+            Access::Open => Ok(false),
+            Access::Passphrase { hash } => {
+                let hash = PasswordHash::new(hash).map_err(|error| WebsocketError::ServiceError(error.to_string()))?;
+                passphrase
+                    .filter(|passphrase| Argon2::default().verify_password(passphrase.as_bytes(), &hash).is_ok())
+                    .map(|_| false)
+                    .ok_or(WebsocketError::IncorrectPassphrase)
+            }
+            Access::InviteOnly { invites } => {
+                let index = invites.iter()
+                    .position(|token| Some(token.as_str()) == invite)
+                    .ok_or(WebsocketError::InvalidInvite)?;
+                invites.remove(index);
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Game {
+    /// Spectators and table devices aren't added to the authorized players
+    pub async fn admit(
+        &mut self,
+        services: &Services,
+        player_id: PlayerId,
+        role: Role,
+        passphrase: Option<&str>,
+        invite: Option<&str>,
+    ) -> Result<(), WebsocketError> {
+        if self.banned.contains(&player_id) {
+            return Err(WebsocketError::Banned);
+        }
+        self.check_room(&player_id, role)?;
+        let invited = !self.authorized_players.contains(&player_id) && self.access.check(passphrase, invite)?;
+
+        // Spectators and table devices aren't added to the players, so they can't be dealt or
+        // given cards
+        if role != Role::Player {
+            if invited {
+                self.save(services).await?;
+            }
+            return Ok(())
+        }
+        Ok(self.add_authorized_player(services, player_id).await?)
+    }
+
+    /// Players who still have a seat can always come back
+    pub fn check_room(&self, player_id: &PlayerId, role: Role) -> Result<(), WebsocketError> {
+        let full = match role {
+            Role::Player => {
+                let seated = self.connected_players.contains_key(player_id) || self.disconnected.contains_key(player_id);
+                !seated && self.is_full()
+            }
+            Role::Spectator => self.settings.max_spectators.is_some_and(|max| self.spectators.len() >= max),
+            // Table devices aren't seated
+            Role::Table => false,
+        };
+        if full {
+            return Err(WebsocketError::GameFull)
+        }
+        Ok(())
+    }
+
+    pub fn is_full(&self) -> bool {
+        let taken = self.connected_players.len() + self.disconnected.len();
+        self.settings.max_players.is_some_and(|max| taken >= max)
+    }
+
+    pub async fn create_invite(&mut self, services: &Services, conn_id: &ConnectionId) -> Result<(), WebsocketError> {
+        let Access::InviteOnly { invites } = &mut self.access else {
+            return Err(WebsocketError::InvalidRequest("the game is not invite only"))
+        };
+        if invites.len() == MAX_INVITES {
+            invites.remove(0);
+        }
+        let invite = format!("invite_{}", Uuid::new_v4());
+        invites.push(invite.clone());
+
+        self.save(services).await?;
+        services.send(conn_id, &WebsocketResponse::Invite {
+            game_id: self.id.clone(),
+            invite,
+        }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Role;
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::{Access, GameSettings};
+    use crate::requests::WebsocketRequest;
+    use crate::WebsocketError;
+
+    #[tokio::test]
+    async fn players_cannot_join_a_full_game_but_can_come_back() {
+        let settings = GameSettings { max_players: Some(2), ..GameSettings::default() };
+        let test = TestGame::new(spades(1), settings, &["a", "b"]).await;
+
+        assert!(test.game().await.is_full());
+        assert!(matches!(test.join("c", Role::Player).await, Err(WebsocketError::GameFull)));
+        test.send("b", WebsocketRequest::LeaveGame).await.unwrap();
+        // b still has a seat while their hand is kept for them
+        assert!(matches!(test.join("c", Role::Player).await, Err(WebsocketError::GameFull)));
+        test.join("b", Role::Player).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn spectators_are_limited() {
+        let settings = GameSettings { max_spectators: Some(1), ..GameSettings::default() };
+        let test = TestGame::new(spades(1), settings, &["a"]).await;
+        test.join("s", Role::Spectator).await.unwrap();
+
+        assert!(matches!(test.join("t", Role::Spectator).await, Err(WebsocketError::GameFull)));
+        test.send_as("s", Role::Spectator, WebsocketRequest::LeaveGame).await.unwrap();
+        test.join("t", Role::Spectator).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn admit_checks_the_passphrase_and_uses_up_invites() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a"]).await;
+        let mut game = test.game().await;
+
+        game.access = Access::new(Some("secret".to_string()), false).unwrap();
+        let result = game.admit(&test.services, "b".to_string(), Role::Player, Some("wrong"), None).await;
+        assert!(matches!(result, Err(WebsocketError::IncorrectPassphrase)));
+        game.admit(&test.services, "b".to_string(), Role::Player, Some("secret"), None).await.unwrap();
+        assert!(game.authorized_players.contains(&"b".to_string()));
+
+        game.access = Access::InviteOnly { invites: vec!["invite".to_string()] };
+        game.admit(&test.services, "c".to_string(), Role::Player, None, Some("invite")).await.unwrap();
+        let result = game.admit(&test.services, "d".to_string(), Role::Player, None, Some("invite")).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidInvite)));
+        // Players who have joined before don't need another invite
+        game.admit(&test.services, "b".to_string(), Role::Player, None, None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn banned_players_are_not_admitted() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b"]).await;
+        test.send("a", WebsocketRequest::Ban { player: "b".to_string(), reclaim: false }).await.unwrap();
+
+        let mut game = test.game().await;
+        let result = game.admit(&test.services, "b".to_string(), Role::Player, None, None).await;
+        assert!(matches!(result, Err(WebsocketError::Banned)));
+    }
+}
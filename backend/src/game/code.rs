@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Error};
+use rand::rng;
+use rand::seq::IndexedRandom;
+use crate::db_utils::Key;
+use crate::game::{Game, GameId};
+use crate::store::StoreError;
+use crate::Services;
+
+/// Leaves out letters that are easily mistaken for each other or for digits (I, L and O)
+const CODE_LETTERS: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ";
+
+pub const CODE_LENGTH: usize = 5;
+
+const MAX_CODE_ATTEMPTS: usize = 10;
+
+pub struct RoomCode;
+
+impl Key for RoomCode {
+    type Key = String;
+    type Value = GameId;
+
+    fn prefix() -> &'static str {
+        "game:code"
+    }
+
+    /// Codes are only written if they don't exist, so two games can't be given the same one
+    fn version(_value: &Self::Value) -> Option<u64> {
+        Some(1)
+    }
+}
+
+pub(super) async fn allocate_code(services: &Services, game_id: &GameId) -> Result<String, Error> {
+    for _ in 0..MAX_CODE_ATTEMPTS {
+        let code = random_code();
+        match services.put::<RoomCode>(&code, game_id).await {
+            Ok(()) => return Ok(code),
+            Err(error) if matches!(error.downcast_ref::<StoreError>(), Some(StoreError::Conflict)) => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Err(anyhow!("couldn't find a free room code"))
+}
+
+fn random_code() -> String {
+    let mut rng = rng();
+    // can safely call unwrap as the letters aren't empty
+    (0..CODE_LENGTH)
+        .map(|_| *CODE_LETTERS.choose(&mut rng).unwrap() as char)
+        .collect()
+}
+
+/// Codes are case insensitive
+pub async fn find_game(services: &Services, id_or_code: &str) -> Option<Game> {
+    if id_or_code.len() == CODE_LENGTH {
+        let game_id = services.get::<RoomCode>(&id_or_code.to_ascii_uppercase()).await?;
+        return services.get::<Game>(&game_id).await
+    }
+    services.get::<Game>(&id_or_code.to_string()).await
+}
+
+impl Game {
+    /// Games replayed from a log have no code
+    pub(super) async fn free_code(&self, services: &Services) {
+        if !self.code.is_empty() {
+            let _ = services.delete::<RoomCode>(&self.code, Some(&self.id)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::{find_game, GameSettings, RoomCode, CODE_LENGTH};
+    use crate::requests::WebsocketRequest;
+
+    #[tokio::test]
+    async fn games_are_found_by_id_or_case_insensitive_code() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a"]).await;
+        let code = test.game().await.code;
+        assert_eq!(code.len(), CODE_LENGTH);
+
+        assert_eq!(find_game(&test.services, &test.game_id).await.unwrap().id, test.game_id);
+        assert_eq!(find_game(&test.services, &code.to_lowercase()).await.unwrap().id, test.game_id);
+
+        // The code is free again once the game ends
+        test.send("a", WebsocketRequest::LeaveGame).await.unwrap();
+        assert!(test.services.get::<RoomCode>(&code).await.is_none());
+    }
+}
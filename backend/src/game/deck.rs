@@ -1,11 +1,12 @@
+use crate::game::{Recorder, TeamId};
 use crate::requests::DeckType;
-use rand::rng;
-use rand::seq::SliceRandom;
+use crate::WebsocketError;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::fmt::{Debug, Display, Formatter};
+use std::iter;
 use strum::Display;
-use uuid::Uuid;
+use thiserror::Error;
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Display)]
@@ -17,7 +18,8 @@ pub enum Suit {
 }
 
 #[repr(u8)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Display)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Display, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub enum Rank {
     Ace = 1,
     Two = 2,
@@ -42,28 +44,52 @@ pub enum SpecialCard {
 }
 
 impl Rank {
-    fn from_u8(val: u8) -> Self {
-        debug_assert!(val & 0b111100 <= 13, "Invalid rank: {}", val);
-        unsafe { std::mem::transmute(val & 0b111100) }
+    pub const ALL: [Rank; 13] = [
+        Rank::Ace, Rank::Two, Rank::Three, Rank::Four, Rank::Five, Rank::Six, Rank::Seven,
+        Rank::Eight, Rank::Nine, Rank::Ten, Rank::Jack, Rank::Queen, Rank::King,
+    ];
+
+}
+
+impl TryFrom<u8> for Rank {
+    type Error = InvalidCardByte;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        val.checked_sub(1)
+            .and_then(|i| Rank::ALL.get(i as usize).copied())
+            .ok_or(InvalidCardByte(val))
     }
 }
 
 impl Suit {
-    fn from_u8(val: u8) -> Self {
-        debug_assert!(val & 0b11 <= 3, "Invalid suit: {}", val);
-        unsafe { std::mem::transmute(val & 0b11) }
+    pub const ALL: [Suit; 4] = [Suit::Spades, Suit::Hearts, Suit::Diamonds, Suit::Clubs];
+
+}
+
+impl TryFrom<u8> for Suit {
+    type Error = InvalidCardByte;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        Suit::ALL.get(val as usize).copied().ok_or(InvalidCardByte(val))
     }
 }
 
 impl SpecialCard {
-    const MAX: u8 = SpecialCard::JokerRed as u8;
+    pub const ALL: [SpecialCard; 2] = [SpecialCard::JokerBlack, SpecialCard::JokerRed];
+}
 
-    fn from_u8(val: u8) -> Self {
-        debug_assert!(val & 0b0011_1111 <= Self::MAX, "Invalid suit: {}", val);
-        unsafe { std::mem::transmute(val & 0b0011_1111) }
+impl TryFrom<u8> for SpecialCard {
+    type Error = InvalidCardByte;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        SpecialCard::ALL.get(val as usize).copied().ok_or(InvalidCardByte(val))
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Error)]
+#[error("`{0}` is not a valid card")]
+pub struct InvalidCardByte(pub u8);
+
 /// If bit 7 is set, represents a face down card
 /// If bit 6 is set, represents a special card
 /// If special card, bits 0-5 represent the special card type
@@ -71,8 +97,23 @@ impl SpecialCard {
 /// (0 - Spade, 1 - Heart, 2 - Diamond, 3 - Club)
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "u8")]
 pub struct Card(u8);
 
+impl TryFrom<u8> for Card {
+    type Error = InvalidCardByte;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        let card = Card(val);
+        let valid = if card.is_special() {
+            SpecialCard::try_from(val & 0b0011_1111).is_ok()
+        } else {
+            card == Card::HIDDEN_CARD || Rank::try_from((val >> 2) & 0b1111).is_ok()
+        };
+        valid.then_some(card).ok_or(InvalidCardByte(val))
+    }
+}
+
 impl Card {
     /// Hidden card sent to clients
     /// Cards that are turned over in the deck will have bot 6 set, so the value is still accessible
@@ -86,16 +127,6 @@ impl Card {
         Self(0b0100_0000 | (kind as u8))
     }
 
-    fn from_u8(val: u8) -> Self {
-        debug_assert!(
-            val & 0b0111_1111 <= 52
-                || (val & 0b0100_0000 != 0 && val & 0b0011_1111 <= SpecialCard::MAX),
-            "Invalid card byte: {}",
-            val
-        );
-        Card(val)
-    }
-
     pub fn is_face_down(&self) -> bool {
         self.0 & 0b1000_0000 != 0
     }
@@ -109,15 +140,15 @@ impl Card {
     }
 
     pub fn kind(&self) -> Option<SpecialCard> {
-        self.is_special().then(|| SpecialCard::from_u8(self.0))
+        self.is_special().then(|| SpecialCard::try_from(self.0 & 0b0011_1111).ok())?
     }
 
     pub fn rank(&self) -> Option<Rank> {
-        self.is_numerical().then(|| Rank::from_u8(self.0))
+        self.is_numerical().then(|| Rank::try_from((self.0 >> 2) & 0b1111).ok())?
     }
 
     pub fn suit(&self) -> Option<Suit> {
-        self.is_numerical().then(|| Suit::from_u8(self.0))
+        self.is_numerical().then(|| Suit::try_from(self.0 & 0b11).ok())?
     }
 
     pub fn flip(&mut self) {
@@ -125,6 +156,13 @@ impl Card {
     }
 }
 
+/// Sends cards as a byte string, so MessagePack keeps each card to a single byte even when
+/// it is face down. JSON still gets an array of numbers
+pub fn serialize_cards<S: Serializer>(cards: &[Card], serializer: S) -> Result<S::Ok, S::Error> {
+    let bytes: Vec<u8> = cards.iter().map(|card| card.0).collect();
+    serializer.serialize_bytes(&bytes)
+}
+
 impl Display for Card {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         if let Some(kind) = self.kind() {
@@ -137,65 +175,310 @@ impl Display for Card {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DeckSpec {
+    pub copies: u8,
+    pub jokers: bool,
+    /// Only these ranks are included, or every rank if not set
+    pub ranks: Option<Vec<Rank>>,
+}
+
+impl Default for DeckSpec {
+    fn default() -> Self {
+        Self {
+            copies: 1,
+            jokers: false,
+            ranks: None,
+        }
+    }
+}
+
+impl DeckSpec {
+    pub const MAX_COPIES: u8 = 8;
+
+    pub(crate) fn validate(&self) -> Result<(), WebsocketError> {
+        if self.copies == 0 || self.copies > Self::MAX_COPIES {
+            return Err(WebsocketError::InvalidRequest("a deck must have between 1 and 8 copies"))
+        }
+        if self.ranks.as_ref().is_some_and(Vec::is_empty) && !self.jokers {
+            return Err(WebsocketError::InvalidRequest("a deck must have at least one card"))
+        }
+        Ok(())
+    }
+
+    fn build(&self, recorder: &mut Recorder) -> Vec<Card> {
+        let ranks = self.ranks.as_deref().unwrap_or(&Rank::ALL);
+        let jokers = if self.jokers { &SpecialCard::ALL[..] } else { &[] };
+        let copy = ranks.iter()
+            .flat_map(|rank| Suit::ALL.map(|suit| Card::numerical(*rank, suit)))
+            .chain(jokers.iter().map(|kind| Card::special(*kind)));
+
+        let mut cards: Vec<_> = iter::repeat_n(copy, self.copies as usize).flatten().collect();
+        for card in &mut cards {
+            card.flip();
+        }
+        recorder.shuffle(&mut cards);
+        cards
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeckPreset {
+    Pinochle,
+    Euchre,
+    Canasta,
+    Piquet,
+}
+
+impl DeckPreset {
+    pub fn spec(&self) -> DeckSpec {
+        use Rank::*;
+        let (copies, jokers, ranks) = match self {
+            DeckPreset::Pinochle => (2, false, Some(vec![Nine, Ten, Jack, Queen, King, Ace])),
+            DeckPreset::Euchre => (1, false, Some(vec![Nine, Ten, Jack, Queen, King, Ace])),
+            DeckPreset::Canasta => (2, true, None),
+            DeckPreset::Piquet => (1, false, Some(vec![Seven, Eight, Nine, Ten, Jack, Queen, King, Ace])),
+        };
+        DeckSpec { copies, jokers, ranks }
+    }
+}
+
 pub type StackId = String;
 pub type Position = (i16, i16);
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Ids are handed out in a random order, so they don't reveal which card they belong to
+pub type CardId = u32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stack {
     pub id: StackId,
     pub cards: Vec<Card>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub card_ids: Vec<CardId>,
     pub position: Position,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<TeamId>,
 }
 
 impl Stack {
-    pub(super) fn from(deck_type: DeckType) -> Vec<Self> {
+    pub(super) fn empty(id: StackId, position: Position) -> Self {
+        Self {
+            id,
+            cards: Vec::new(),
+            card_ids: Vec::new(),
+            position,
+            team: None,
+        }
+    }
+
+    pub(super) fn from(
+        deck_type: DeckType,
+        card_ids: bool,
+        next_id: &mut u64,
+        recorder: &mut Recorder,
+    ) -> Result<Vec<Self>, InvalidCardByte> {
         let stacks = match deck_type {
-            DeckType::Standard => {
-                let mut cards: Vec<_> = (4..=55).map(Card::from_u8).collect();
-                // Turn every card face down
-                for card in &mut cards {
-                    card.0 |= 0b1000_0000;
-                }
-                cards.shuffle(&mut rng());
-                vec![cards]
-            }
-            DeckType::Custom { stacks } => {
-                // todo Check card validity
-                stacks
-            }
+            DeckType::Standard => vec![DeckSpec::default().build(recorder)],
+            DeckType::Spec(spec) => vec![spec.build(recorder)],
+            DeckType::Preset { preset } => vec![preset.spec().build(recorder)],
+            DeckType::Custom { stacks } => stacks.into_iter()
+                .map(|stack| stack.into_iter().map(Card::try_from).collect())
+                .collect::<Result<_, _>>()?,
         };
 
-        stacks
+        let mut ids: Vec<CardId> = Vec::new();
+        if card_ids {
+            ids = (0..stacks.iter().map(Vec::len).sum::<usize>() as CardId).collect();
+            recorder.shuffle(&mut ids);
+        }
+        let mut ids = ids.into_iter();
+
+        Ok(stacks
             .into_iter()
             .enumerate()
             .map(|(i, cards)| Self {
-                id: Uuid::new_v4().to_string(),
+                id: new_stack_id(next_id),
+                card_ids: ids.by_ref().take(if card_ids { cards.len() } else { 0 }).collect(),
                 cards,
-                position: (200 + 250 * (i % 3) as i16, 200 + 250 * (i / 3) as i16),
+                position: Self::grid_position(i),
+                team: None,
             })
-            .collect()
+            .collect())
+    }
+
+    pub(super) fn pop(&mut self) -> Option<(Card, Option<CardId>)> {
+        let card = self.cards.pop()?;
+        Some((card, self.card_ids.pop()))
+    }
+
+    pub(super) fn push(&mut self, card: Card, id: Option<CardId>) {
+        self.cards.push(card);
+        self.card_ids.extend(id);
+    }
+
+    pub(super) fn append(&mut self, cards: &mut Vec<Card>, ids: &mut Vec<CardId>) {
+        self.cards.append(cards);
+        self.card_ids.append(ids);
+    }
+
+    pub(super) fn reverse(&mut self) {
+        self.cards.reverse();
+        self.card_ids.reverse();
+    }
+
+    pub(super) fn shuffle(&mut self, recorder: &mut Recorder) {
+        if self.card_ids.is_empty() {
+            recorder.shuffle(&mut self.cards);
+            return
+        }
+        // Face down cards take the ids in the order they were in, so the ids don't show where
+        // each card went
+        let face_down_ids: Vec<CardId> = self.cards.iter()
+            .zip(&self.card_ids)
+            .filter(|(card, _)| card.is_face_down())
+            .map(|(_, id)| *id)
+            .collect();
+        let mut cards: Vec<(Card, CardId)> = self.cards.drain(..).zip(self.card_ids.drain(..)).collect();
+        recorder.shuffle(&mut cards);
+        (self.cards, self.card_ids) = cards.into_iter().unzip();
+
+        let face_down = self.cards.iter()
+            .zip(&mut self.card_ids)
+            .filter(|(card, _)| card.is_face_down())
+            .map(|(_, id)| id);
+        for (id, face_down_id) in face_down.zip(face_down_ids) {
+            *id = face_down_id;
+        }
+    }
+
+    pub(super) fn grid_position(i: usize) -> Position {
+        (200 + 250 * (i % 3) as i16, 200 + 250 * (i / 3) as i16)
     }
 
     pub(super) fn state(&self) -> StackState {
+        // Team members are sent the cards separately
         let top_card = match self.cards.last().cloned() {
-            Some(card) if !card.is_face_down() => card,
+            Some(card) if !card.is_face_down() && self.team.is_none() => card,
             _ => Card::HIDDEN_CARD,
         };
 
         StackState {
             stack_id: self.id.clone(),
-            position: self.position.clone(),
+            position: self.position,
             visible_card: top_card,
             remaining_cards: self.cards.len(),
+            card_ids: (!self.card_ids.is_empty()).then(|| self.card_ids.clone()),
+            team: self.team.clone(),
         }
     }
 }
 
-#[derive(Debug, Serialize, PartialEq, JsonSchema)]
+/// Takes the next id from the game's counter. Ids are handed out in order rather than randomly so
+/// a replayed game ends up with the same ones
+pub(super) fn new_stack_id(next_id: &mut u64) -> StackId {
+    *next_id += 1;
+    next_id.to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StackState {
     pub stack_id: StackId,
     pub position: Position,
     pub visible_card: Card,
     pub remaining_cards: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_ids: Option<Vec<CardId>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<TeamId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    #[test]
+    fn presets_have_the_expected_number_of_cards() {
+        for (preset, count) in [
+            (DeckPreset::Pinochle, 48),
+            (DeckPreset::Euchre, 24),
+            (DeckPreset::Canasta, 108),
+            (DeckPreset::Piquet, 32),
+        ] {
+            let cards = preset.spec().build(&mut Recorder::default());
+            assert_eq!(cards.len(), count, "{preset:?}");
+            assert!(cards.iter().all(Card::is_face_down), "{preset:?}");
+        }
+    }
+
+    #[test]
+    fn spec_includes_each_card_once_per_copy() {
+        let spec = DeckSpec { copies: 3, jokers: true, ranks: Some(vec![Rank::Ace]) };
+        let mut cards: Vec<u8> = spec.build(&mut Recorder::default()).iter().map(|card| card.0).collect();
+        cards.sort();
+
+        let mut copy: Vec<u8> = Suit::ALL.iter().map(|suit| Card::numerical(Rank::Ace, *suit))
+            .chain(SpecialCard::ALL.map(Card::special))
+            .map(|mut card| {
+                card.flip();
+                card.0
+            })
+            .collect();
+        copy.sort();
+        assert_eq!(cards, copy.iter().flat_map(|card| [*card; 3]).collect::<Vec<_>>());
+        assert_eq!(DeckSpec::default().build(&mut Recorder::default()).len(), 52);
+    }
+
+    #[test]
+    fn deserializing_cards_rejects_bytes_that_are_not_cards() {
+        for byte in [0, 63, 0b0100_0010, 0b1000_0001] {
+            assert!(serde_json::from_value::<Card>(byte.into()).is_err(), "{byte}");
+        }
+        for byte in [4, 55, 0b1000_0100, 0b0100_0001, Card::HIDDEN_CARD.0] {
+            assert_eq!(serde_json::from_value::<Card>(byte.into()).unwrap().0, byte);
+        }
+    }
+
+    #[test]
+    fn specs_must_make_at_least_one_card() {
+        let spec = |copies, jokers, ranks| DeckSpec { copies, jokers, ranks };
+        assert!(spec(0, true, None).validate().is_err());
+        assert!(spec(DeckSpec::MAX_COPIES + 1, false, None).validate().is_err());
+        assert!(spec(1, false, Some(vec![])).validate().is_err());
+
+        spec(DeckSpec::MAX_COPIES, false, None).validate().unwrap();
+        spec(1, true, Some(vec![])).validate().unwrap();
+    }
+
+    #[test]
+    fn building_a_custom_deck_fails_on_an_invalid_card() {
+        let deck = DeckType::Custom { stacks: vec![vec![4], vec![8, 63]] };
+        let result = Stack::from(deck, false, &mut 0, &mut Recorder::default());
+        assert_eq!(result.unwrap_err(), InvalidCardByte(63));
+    }
+
+    #[test]
+    fn shuffling_keeps_each_face_up_card_with_its_id() {
+        let deck = DeckType::Custom { stacks: vec![(1..=13).map(|rank| rank << 2).collect()] };
+        let mut stack = Stack::from(deck, true, &mut 0, &mut Recorder::default()).unwrap().remove(0);
+        let ids: HashMap<u8, CardId> = stack.cards.iter().map(|card| card.0).zip(stack.card_ids.iter().copied()).collect();
+
+        stack.shuffle(&mut Recorder::default());
+        for (card, id) in stack.cards.iter().zip(&stack.card_ids) {
+            assert_eq!(ids[&card.0], *id);
+        }
+    }
+
+    #[test]
+    fn shuffling_face_down_cards_leaves_their_ids_in_place() {
+        let deck = DeckType::Custom { stacks: vec![(1..=13).map(|rank| rank << 2 | 0b1000_0000).collect()] };
+        let mut stack = Stack::from(deck, true, &mut 0, &mut Recorder::default()).unwrap().remove(0);
+        let ids = stack.card_ids.clone();
+
+        stack.shuffle(&mut Recorder::default());
+        assert_eq!(stack.card_ids, ids);
+    }
 }
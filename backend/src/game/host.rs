@@ -0,0 +1,119 @@
+use crate::game::{Game, PlayerId};
+use crate::requests::GameStateData;
+use crate::requests::WebsocketRequestDiscriminants::{SetCoHost, TransferOwnership};
+use crate::{Services, WebsocketError};
+
+impl Game {
+    pub fn is_host(&self, player_id: &PlayerId) -> bool {
+        self.owner == *player_id || self.co_hosts.contains(player_id)
+    }
+
+    /// The first co-host still connected, otherwise the first player in the seat order
+    pub(super) fn successor(&self) -> Option<PlayerId> {
+        self.co_hosts.iter()
+            .find(|co_host| self.connected_players.contains_key(*co_host))
+            .or_else(|| self.seat_order.first())
+            .or_else(|| self.connected_players.keys().min())
+            .cloned()
+    }
+
+    pub async fn transfer_ownership(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        new_owner: PlayerId,
+    ) -> Result<(), WebsocketError> {
+        if self.owner != *player_id {
+            return Err(WebsocketError::NoPermission)
+        }
+        if !self.connected_players.contains_key(&new_owner) {
+            return Err(WebsocketError::PlayerNotFound)
+        }
+
+        self.owner = new_owner.clone();
+        self.co_hosts.retain(|co_host| *co_host != new_owner);
+        self.commit(services, [], GameStateData {
+            cause_action: Some(TransferOwnership),
+            cause_player: Some(player_id.clone()),
+            owner: Some(new_owner),
+            co_hosts: Some(self.co_hosts.clone()),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+
+    /// Only the owner can choose co-hosts
+    pub async fn set_co_host(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        player: PlayerId,
+        enabled: bool,
+    ) -> Result<(), WebsocketError> {
+        if self.owner != *player_id {
+            return Err(WebsocketError::NoPermission)
+        }
+
+        if enabled {
+            if player == self.owner || !self.authorized_players.contains(&player) {
+                return Err(WebsocketError::PlayerNotFound)
+            }
+            if !self.co_hosts.contains(&player) {
+                self.co_hosts.push(player);
+            }
+        } else {
+            self.co_hosts.retain(|co_host| *co_host != player);
+        }
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(SetCoHost),
+            cause_player: Some(player_id.clone()),
+            co_hosts: Some(self.co_hosts.clone()),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::GameSettings;
+    use crate::requests::WebsocketRequest;
+    use crate::WebsocketError;
+
+    fn co_host(player: &str) -> WebsocketRequest {
+        WebsocketRequest::SetCoHost { player: player.to_string(), enabled: true }
+    }
+
+    #[tokio::test]
+    async fn the_first_connected_co_host_takes_over_then_the_first_seat() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b", "c", "d"]).await;
+        test.send("a", co_host("c")).await.unwrap();
+        test.send("a", co_host("d")).await.unwrap();
+        test.send("c", WebsocketRequest::LeaveGame).await.unwrap();
+
+        test.send("a", WebsocketRequest::LeaveGame).await.unwrap();
+        assert_eq!(test.game().await.owner, "d");
+
+        test.send("d", WebsocketRequest::LeaveGame).await.unwrap();
+        assert_eq!(test.game().await.owner, "b");
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_can_transfer_ownership() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b", "c"]).await;
+        test.send("a", co_host("b")).await.unwrap();
+
+        let transfer = |player: &str| WebsocketRequest::TransferOwnership { player: player.to_string() };
+        assert!(matches!(test.send("b", transfer("b")).await, Err(WebsocketError::NoPermission)));
+        assert!(matches!(test.send("a", transfer("z")).await, Err(WebsocketError::PlayerNotFound)));
+
+        test.send("a", transfer("b")).await.unwrap();
+        let game = test.game().await;
+        assert_eq!(game.owner, "b");
+        assert!(game.co_hosts.is_empty());
+        assert!(game.connected_players.contains_key("a"));
+        assert!(matches!(test.send("a", co_host("c")).await, Err(WebsocketError::NoPermission)));
+    }
+}
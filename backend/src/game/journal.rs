@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use crate::game::{Card, CardId, Game, Player, PlayerId, Stack, StackId, StackState};
+use crate::requests::{GameStateData, WebsocketRequestDiscriminants};
+use crate::requests::WebsocketRequestDiscriminants::{Redo, Undo};
+use crate::{Services, WebsocketError};
+
+pub const MAX_JOURNAL_ENTRIES: usize = 20;
+
+/// The journal is stored in the game item, which the store limits the size of
+pub const MAX_JOURNAL_BYTES: usize = 64 * 1024;
+
+/// `None` if the stack didn't exist
+pub type StacksSnapshot = Vec<(StackId, Option<Stack>)>;
+
+pub type HandSnapshot = (Vec<Card>, Vec<CardId>);
+
+/// Restoring the exact cards means shuffles are undone to the same order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub player: PlayerId,
+    pub action: WebsocketRequestDiscriminants,
+    pub before: StacksSnapshot,
+    pub after: StacksSnapshot,
+    pub hand: Option<(HandSnapshot, HandSnapshot)>,
+    #[serde(default)]
+    pub size: usize,
+}
+
+impl Game {
+    pub(super) fn capture(&self, stack_ids: &[StackId]) -> StacksSnapshot {
+        stack_ids.iter()
+            .map(|id| (id.clone(), self.stacks.iter().find(|s| s.id == *id).cloned()))
+            .collect()
+    }
+
+    /// `stack_ids` are the stacks that exist after the action which weren't captured before it
+    pub(super) fn journal(
+        &mut self,
+        player: &PlayerId,
+        action: WebsocketRequestDiscriminants,
+        before: StacksSnapshot,
+        stack_ids: &[StackId],
+        hand: Option<(HandSnapshot, HandSnapshot)>,
+    ) {
+        let mut before = before;
+        for id in stack_ids {
+            if !before.iter().any(|(before_id, _)| before_id == id) {
+                before.push((id.clone(), None));
+            }
+        }
+        let ids: Vec<StackId> = before.iter().map(|(id, _)| id.clone()).collect();
+        let after = self.capture(&ids);
+
+        let mut entry = JournalEntry { player: player.clone(), action, before, after, hand, size: 0 };
+        entry.size = serde_json::to_vec(&entry).map_or(MAX_JOURNAL_BYTES + 1, |bytes| bytes.len());
+        self.journal.push_back(entry);
+        self.undone.clear();
+
+        let mut total: usize = self.journal.iter().map(|entry| entry.size).sum();
+        while self.journal.len() > MAX_JOURNAL_ENTRIES || total > MAX_JOURNAL_BYTES {
+            // can safely call unwrap as the limits are only passed while there are entries
+            total -= self.journal.pop_front().unwrap().size;
+        }
+    }
+
+    pub(super) fn clear_journal(&mut self) {
+        self.journal.clear();
+        self.undone.clear();
+    }
+
+    pub async fn undo(&mut self, services: &Services, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        let entry = self.journal.back().ok_or(WebsocketError::InvalidRequest("there is nothing to undo"))?;
+        if entry.player != *player_id && !self.is_host(player_id) {
+            return Err(WebsocketError::NoPermission)
+        }
+
+        // Move the entry before saving, so it can be redone
+        let entry = self.journal.pop_back().unwrap();
+        let (entry_player, stacks) = (entry.player.clone(), entry.before.clone());
+        let hand = entry.hand.clone().map(|(before, _)| before);
+        self.undone.push(entry);
+        self.apply_entry(services, player_id, Undo, &entry_player, &stacks, hand).await
+    }
+
+    pub async fn redo(&mut self, services: &Services, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        let entry = self.undone.last().ok_or(WebsocketError::InvalidRequest("there is nothing to redo"))?;
+        if entry.player != *player_id && !self.is_host(player_id) {
+            return Err(WebsocketError::NoPermission)
+        }
+
+        let entry = self.undone.pop().unwrap();
+        let (entry_player, stacks) = (entry.player.clone(), entry.after.clone());
+        let hand = entry.hand.clone().map(|(_, after)| after);
+        self.journal.push_back(entry);
+        self.apply_entry(services, player_id, Redo, &entry_player, &stacks, hand).await
+    }
+
+    async fn apply_entry(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        action: WebsocketRequestDiscriminants,
+        entry_player: &PlayerId,
+        stacks: &StacksSnapshot,
+        hand: Option<HandSnapshot>,
+    ) -> Result<(), WebsocketError> {
+        let states = self.restore(stacks);
+
+        let mut player: Option<Player> = None;
+        if let Some((cards, ids)) = hand {
+            let mut entry_player = self.get_player(services, entry_player).await?;
+            if entry_player.game_id != self.id {
+                return Err(WebsocketError::PlayerNotFound)
+            }
+            entry_player.hand = cards;
+            entry_player.hand_ids = ids;
+            player = Some(entry_player);
+        }
+
+        self.commit(services, player.as_mut(), GameStateData {
+            cause_action: Some(action),
+            cause_player: Some(player_id.clone()),
+            stacks: Some(states),
+            ..Default::default()
+        }).await?;
+
+        if let Some(player) = player
+            && let Some(conn_id) = self.connected_players.get(&player.player_id)
+        {
+            player.send_state(services, conn_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Stacks that didn't exist are removed, which is sent as the stack being empty
+    fn restore(&mut self, snapshot: &StacksSnapshot) -> Vec<StackState> {
+        let mut states = Vec::with_capacity(snapshot.len());
+        for (id, stack) in snapshot {
+            let index = self.stacks.iter().position(|s| s.id == *id);
+            match (index, stack) {
+                (Some(index), Some(stack)) => {
+                    self.stacks[index] = stack.clone();
+                    states.push(stack.state());
+                }
+                (Some(index), None) => {
+                    let mut removed = self.stacks.remove(index);
+                    removed.cards.clear();
+                    removed.card_ids.clear();
+                    states.push(removed.state());
+                }
+                (None, Some(stack)) => {
+                    self.stacks.push(stack.clone());
+                    states.push(stack.state());
+                }
+                (None, None) => {}
+            }
+        }
+        states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spade, spades, TestGame};
+    use crate::game::{GameSettings, MAX_JOURNAL_BYTES, MAX_JOURNAL_ENTRIES};
+    use crate::requests::DeckType;
+    use crate::requests::WebsocketRequest;
+    use crate::WebsocketError;
+
+    async fn take_card(test: &TestGame, player: &str) -> Result<(), WebsocketError> {
+        let stack = test.game().await.stacks[0].id.clone();
+        test.send(player, WebsocketRequest::TakeCard { stack }).await
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_move_the_card_between_the_hand_and_the_stack() {
+        let test = TestGame::new(spades(3), GameSettings::default(), &["a"]).await;
+        take_card(&test, "a").await.unwrap();
+
+        test.send("a", WebsocketRequest::Undo).await.unwrap();
+        assert!(test.hand("a").await.is_empty());
+        assert_eq!(test.game().await.stacks[0].cards, [spade(1), spade(2), spade(3)]);
+
+        test.send("a", WebsocketRequest::Redo).await.unwrap();
+        assert_eq!(test.hand("a").await, [spade(3)]);
+        assert_eq!(test.game().await.stacks[0].cards, [spade(1), spade(2)]);
+
+        let result = test.send("a", WebsocketRequest::Redo).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("there is nothing to redo"))));
+    }
+
+    #[tokio::test]
+    async fn only_hosts_can_undo_other_players_actions() {
+        let test = TestGame::new(spades(3), GameSettings::default(), &["a", "b"]).await;
+        take_card(&test, "a").await.unwrap();
+
+        let result = test.send("b", WebsocketRequest::Undo).await;
+        assert!(matches!(result, Err(WebsocketError::NoPermission)));
+        assert_eq!(test.hand("a").await, [spade(3)]);
+
+        take_card(&test, "b").await.unwrap();
+        test.send("a", WebsocketRequest::Undo).await.unwrap();
+        assert!(test.hand("b").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_new_action_clears_the_actions_that_were_undone() {
+        let test = TestGame::new(spades(3), GameSettings::default(), &["a"]).await;
+        take_card(&test, "a").await.unwrap();
+        test.send("a", WebsocketRequest::Undo).await.unwrap();
+        let stack = test.game().await.stacks[0].id.clone();
+        test.send("a", WebsocketRequest::FlipStack { stack }).await.unwrap();
+
+        let result = test.send("a", WebsocketRequest::Redo).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("there is nothing to redo"))));
+    }
+
+    #[tokio::test]
+    async fn dealing_cannot_be_undone() {
+        let test = TestGame::new(spades(3), GameSettings::default(), &["a"]).await;
+        take_card(&test, "a").await.unwrap();
+        let stack = test.game().await.stacks[0].id.clone();
+        test.send("a", WebsocketRequest::Deal { stack, count: None, starting_player: None }).await.unwrap();
+
+        let result = test.send("a", WebsocketRequest::Undo).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("there is nothing to undo"))));
+        assert_eq!(test.hand("a").await, [spade(3), spade(2), spade(1)]);
+    }
+
+    #[tokio::test]
+    async fn the_oldest_actions_are_forgotten_once_the_journal_is_too_big() {
+        let deck = DeckType::Custom { stacks: vec![vec![4; 1000]] };
+        let test = TestGame::new(deck, GameSettings::default(), &["a"]).await;
+        let stack = test.game().await.stacks[0].id.clone();
+        for _ in 0..MAX_JOURNAL_ENTRIES {
+            test.send("a", WebsocketRequest::FlipStack { stack: stack.clone() }).await.unwrap();
+        }
+
+        let journal = test.game().await.journal;
+        assert!(journal.len() < MAX_JOURNAL_ENTRIES);
+        assert!(journal.iter().map(|entry| entry.size).sum::<usize>() <= MAX_JOURNAL_BYTES);
+        test.send("a", WebsocketRequest::Undo).await.unwrap();
+    }
+}
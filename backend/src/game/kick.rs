@@ -0,0 +1,129 @@
+use crate::game::{now, Game, Player, PlayerId};
+use crate::requests::{GameStateData, WebsocketResponse};
+use crate::requests::WebsocketRequestDiscriminants::{Ban, Kick};
+use crate::{Services, WebsocketError};
+
+impl Game {
+    /// Players can be banned even once they've left
+    pub async fn kick(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        target: PlayerId,
+        reclaim: bool,
+        ban: bool,
+    ) -> Result<(), WebsocketError> {
+        if target == *player_id {
+            return Err(WebsocketError::InvalidRequest("cannot kick yourself"))
+        }
+        // Co-hosts can kick, but not the owner
+        if target == self.owner {
+            return Err(WebsocketError::NoPermission)
+        }
+
+        let mut data = GameStateData {
+            cause_action: Some(if ban { Ban } else { Kick }),
+            cause_player: Some(target.clone()),
+            ..Default::default()
+        };
+        let conn_id = if let Some(conn_id) = self.connected_players.remove(&target) {
+            self.update_seat_order();
+            data.seat_order = Some(self.seat_order.clone());
+            data.turn = self.turn.clone();
+            Some(conn_id)
+        } else if let Some(conn_id) = self.spectators.remove(&target) {
+            data.spectators = Some(self.spectator_list());
+            Some(conn_id)
+        } else if let Some(conn_id) = self.tables.remove(&target) {
+            data.tables = Some(self.table_list());
+            Some(conn_id)
+        } else {
+            None
+        };
+        if conn_id.is_none() && !ban {
+            return Err(WebsocketError::PlayerNotFound)
+        }
+
+        if ban {
+            if !self.banned.contains(&target) {
+                self.banned.push(target.clone());
+            }
+            self.authorized_players.retain(|player| *player != target);
+            if self.co_hosts.contains(&target) {
+                self.co_hosts.retain(|co_host| *co_host != target);
+                data.co_hosts = Some(self.co_hosts.clone());
+            }
+            if self.table_controls.contains(&target) {
+                self.table_controls.retain(|table| *table != target);
+                data.table_controls = Some(self.table_controls.clone());
+            }
+        }
+
+        let mut player: Option<Player> = services.get::<Player>(&target)
+            .await
+            .filter(|player| player.game_id == self.id && !player.hand.is_empty());
+        if reclaim && let Some(player) = player.as_mut() {
+            data.stacks = Some(vec![self.return_hand(player)]);
+            self.clear_journal();
+        }
+        if reclaim || ban {
+            self.disconnected.remove(&target);
+        } else if player.is_some() {
+            // Kicked players keep their hand until it's reclaimed like anyone else who leaves
+            self.disconnected.insert(target.clone(), now());
+        }
+        // Players only keep their seat while they have a hand
+        if !self.disconnected.contains_key(&target) && self.free_seat(&target) {
+            data.seats = Some(self.seats.clone());
+            data.seat_order = Some(self.seat_order.clone());
+            data.turn = self.turn.clone();
+        }
+
+        let discard = ban && !reclaim;
+        self.commit(services, player.as_mut().filter(|_| reclaim), data).await?;
+        if discard && player.is_some() {
+            let _ = self.delete_player(services, &target).await;
+        }
+
+        if let Some(conn_id) = conn_id {
+            services.send(&conn_id, &WebsocketResponse::Kicked { game_id: self.id.clone(), banned: ban }).await?;
+            let _ = services.delete_connection(&conn_id.id).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::GameSettings;
+    use crate::requests::{WebsocketRequest, WebsocketResponse};
+    use crate::WebsocketError;
+
+    #[tokio::test]
+    async fn kicked_players_keep_their_hand_and_can_come_back() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        let stack = test.game().await.stacks[0].id.clone();
+        test.send("a", WebsocketRequest::Deal { stack, count: None, starting_player: None }).await.unwrap();
+
+        let kick = WebsocketRequest::Kick { player: "a".to_string(), reclaim: false };
+        assert!(matches!(test.send("b", kick).await, Err(WebsocketError::NoPermission)));
+        test.send("a", WebsocketRequest::Kick { player: "b".to_string(), reclaim: false }).await.unwrap();
+
+        assert!(test.responses("b").contains(&WebsocketResponse::Kicked { game_id: test.game_id.clone(), banned: false }));
+        assert!(test.game().await.disconnected.contains_key("b"));
+        test.send("b", WebsocketRequest::JoinGame).await.unwrap();
+        assert_eq!(test.hand("b").await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn banned_players_cannot_join_again() {
+        let test = TestGame::new(spades(4), GameSettings::default(), &["a", "b"]).await;
+        test.send("a", WebsocketRequest::Ban { player: "b".to_string(), reclaim: false }).await.unwrap();
+
+        let game = test.game().await;
+        assert_eq!(game.banned, ["b"]);
+        assert!(!game.authorized_players.contains(&"b".to_string()));
+        assert!(matches!(test.send("b", WebsocketRequest::JoinGame).await, Err(WebsocketError::Banned)));
+    }
+}
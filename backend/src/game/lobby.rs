@@ -0,0 +1,99 @@
+use anyhow::Error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::game::{Access, Game, GameId};
+use crate::requests::DeckType;
+use crate::store::IndexEntry;
+use crate::Services;
+
+pub const DEFAULT_LIST_LIMIT: usize = 20;
+
+pub const MAX_LIST_LIMIT: usize = 50;
+
+const LOBBY: &str = "lobby";
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GameListing {
+    pub game_id: GameId,
+    pub code: String,
+    pub name: String,
+    pub players: usize,
+    pub max_players: Option<usize>,
+    pub deck_type: DeckType,
+    /// Unix time in seconds
+    pub created_at: u64,
+    pub passphrase: bool,
+}
+
+impl From<&Game> for GameListing {
+    fn from(game: &Game) -> Self {
+        Self {
+            game_id: game.id.clone(),
+            code: game.code.clone(),
+            name: game.name.clone(),
+            players: game.connected_players.len(),
+            max_players: game.settings.max_players,
+            deck_type: game.deck_type.clone(),
+            created_at: game.created_at,
+            passphrase: matches!(game.access, Access::Passphrase { .. }),
+        }
+    }
+}
+
+pub async fn list_games(services: &Services, cursor: Option<&str>, limit: usize) -> Result<(Vec<GameListing>, Option<String>), Error> {
+    services.query(LOBBY, cursor, limit).await
+}
+
+impl Game {
+    /// Saved with the game, so the listing is kept up to date and is removed with it
+    pub(super) fn listing(&self) -> Result<Option<IndexEntry>, Error> {
+        if !self.public || self.is_full() {
+            return Ok(None)
+        }
+        Ok(Some(IndexEntry {
+            partition: LOBBY.to_string(),
+            sort: self.created_at,
+            value: serde_json::to_string(&GameListing::from(self))?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::{list_games, Access, Game, GameSettings};
+    use crate::Services;
+
+    /// Games are listed by when they were created, which is only kept to the second
+    async fn create(services: &Services, name: &str, public: bool, created_at: u64) -> Game {
+        let mut game = Game::new(services, "a".to_string(), name.to_string(), public, spades(1), GameSettings::default(), Access::Open)
+            .await
+            .unwrap();
+        game.created_at = created_at;
+        game.save(services).await.unwrap();
+        game
+    }
+
+    fn names(page: &[crate::game::GameListing]) -> Vec<&str> {
+        page.iter().map(|listing| listing.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn public_games_are_listed_newest_first_until_they_end() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a"]).await;
+        create(&test.services, "first", true, 1).await;
+        let second = create(&test.services, "second", true, 2).await;
+        create(&test.services, "private", false, 3).await;
+        create(&test.services, "third", true, 4).await;
+
+        let (page, cursor) = list_games(&test.services, None, 2).await.unwrap();
+        assert_eq!(names(&page), ["third", "second"]);
+        let (page, cursor) = list_games(&test.services, cursor.as_deref(), 2).await.unwrap();
+        assert_eq!(names(&page), ["first"]);
+        assert_eq!(cursor, None);
+
+        second.destroy(&test.services).await.unwrap();
+        assert_eq!(names(&list_games(&test.services, None, 10).await.unwrap().0), ["third", "first"]);
+    }
+}
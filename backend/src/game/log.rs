@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::mem;
+use anyhow::{anyhow, Error};
+use rand::rng;
+use rand::seq::SliceRandom;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::db_entry;
+use crate::db_utils::Key;
+use crate::game::{Game, GameId, GameSettings, PlayerId};
+use crate::requests::{DeckType, GameStateData, WebsocketRequest};
+use crate::store::Append;
+use crate::Services;
+
+/// How many events are stored together. The log is split up so it can keep growing without
+/// any one item reaching the store's size limit
+pub const LOG_CHUNK_EVENTS: u64 = 50;
+
+pub type Permutation = Vec<u32>;
+
+/// The log of a game holds everything needed to replay it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+#[serde(rename_all = "kebab-case")]
+#[serde(rename_all_fields = "camelCase")]
+pub enum LogEvent {
+    Created {
+        game_id: GameId,
+        created_at: u64,
+        owner: PlayerId,
+        deck_type: DeckType,
+        settings: GameSettings,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        shuffles: Vec<Permutation>,
+    },
+    /// Requests made by the server rather than a player have no player
+    Request {
+        player: Option<PlayerId>,
+        request: WebsocketRequest,
+        update: Box<GameStateData>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        shuffles: Vec<Permutation>,
+    },
+}
+
+impl LogEvent {
+    pub fn seq(&self) -> u64 {
+        match self {
+            LogEvent::Created { .. } => 0,
+            LogEvent::Request { update, .. } => update.seq,
+        }
+    }
+}
+
+// Keyed by `{game_id}:{chunk}`, see `LOG_CHUNK_EVENTS`
+db_entry!(EventLog, String, LogEvent, "game:log");
+
+fn log_key(game_id: &GameId, seq: u64) -> String {
+    format!("{game_id}:{}", seq / LOG_CHUNK_EVENTS)
+}
+
+/// The log is kept after the game is destroyed
+pub async fn read_log(services: &Services, game_id: &GameId) -> Result<Vec<LogEvent>, Error> {
+    let mut events = Vec::new();
+    for chunk in 0.. {
+        let key = log_key(game_id, chunk * LOG_CHUNK_EVENTS);
+        let chunk_events = services.list::<EventLog>(&key).await?;
+        if chunk_events.is_empty() {
+            break
+        }
+        events.extend(chunk_events);
+    }
+    Ok(events)
+}
+
+#[derive(Debug, Default)]
+pub struct Recorder {
+    cause: Option<(Option<PlayerId>, WebsocketRequest)>,
+    shuffles: Vec<Permutation>,
+    pending: Vec<LogEvent>,
+    replay: Option<VecDeque<Permutation>>,
+}
+
+impl Recorder {
+    /// Shuffles the items, or puts them in the next recorded order when replaying. If the
+    /// recorded order doesn't fit, a random one is used, which is caught when the replayed log is
+    /// compared to the original
+    pub(super) fn shuffle<T>(&mut self, items: &mut Vec<T>) {
+        if items.len() < 2 {
+            return
+        }
+        let recorded = self.replay.as_mut()
+            .and_then(VecDeque::pop_front)
+            .filter(|permutation| is_permutation(permutation, items.len()));
+        let permutation = recorded.unwrap_or_else(|| {
+            let mut permutation: Permutation = (0..items.len() as u32).collect();
+            permutation.shuffle(&mut rng());
+            permutation
+        });
+
+        let mut taken: Vec<Option<T>> = items.drain(..).map(Some).collect();
+        // can safely call unwrap as each index is used exactly once
+        items.extend(permutation.iter().map(|&i| taken[i as usize].take().unwrap()));
+        self.shuffles.push(permutation);
+    }
+
+    pub(super) fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+}
+
+fn is_permutation(permutation: &Permutation, len: usize) -> bool {
+    let mut seen = vec![false; len];
+    permutation.len() == len && permutation.iter().all(|&i| {
+        seen.get_mut(i as usize).is_some_and(|seen| !mem::replace(seen, true))
+    })
+}
+
+impl Game {
+    pub fn handling(&mut self, player: Option<&PlayerId>, request: &WebsocketRequest) {
+        self.recorder.cause = Some((player.cloned(), request.clone()));
+    }
+
+    pub fn replay_shuffles(&mut self, shuffles: Vec<Permutation>) {
+        self.recorder.replay = Some(shuffles.into());
+    }
+
+    pub(super) fn log_created(&mut self) {
+        let event = LogEvent::Created {
+            game_id: self.id.clone(),
+            created_at: self.created_at,
+            owner: self.owner.clone(),
+            deck_type: self.deck_type.clone(),
+            settings: self.settings.clone(),
+            shuffles: mem::take(&mut self.recorder.shuffles),
+        };
+        self.recorder.pending.push(event);
+    }
+
+    /// Only the first update a request causes is logged with it
+    pub(super) fn log_update(&mut self, update: &GameStateData) {
+        let Some((player, request)) = self.recorder.cause.take() else {
+            return
+        };
+        self.recorder.pending.push(LogEvent::Request {
+            player,
+            request,
+            update: Box::new(update.clone()),
+            shuffles: mem::take(&mut self.recorder.shuffles),
+        });
+    }
+
+    pub(super) fn take_log(&mut self) -> Result<Vec<Append>, Error> {
+        self.recorder.pending
+            .drain(..)
+            .map(|event| EventLog::append(&log_key(&self.id, event.seq()), &event))
+            .collect()
+    }
+
+    pub async fn from_log(services: &Services, event: &LogEvent) -> Result<Self, Error> {
+        let LogEvent::Created { game_id, created_at, owner, deck_type, settings, shuffles } = event else {
+            return Err(anyhow!("the log doesn't start with the game being created"))
+        };
+        let recorder = Recorder {
+            replay: Some(shuffles.clone().into()),
+            ..Default::default()
+        };
+        let mut game = Game::create(
+            game_id.clone(),
+            *created_at,
+            owner.clone(),
+            deck_type.clone(),
+            settings.clone(),
+            recorder,
+        )?;
+        game.save(services).await?;
+        Ok(game)
+    }
+}
@@ -1,8 +1,9 @@
 use std::marker::PhantomData;
 use anyhow::Error;
 use serde::{Deserialize, Serialize};
+use crate::broadcast::ConnectionId;
 use crate::db_utils::{Key};
-use crate::game::{Card, Game, GameId};
+use crate::game::{Card, CardId, Game, GameId};
 use crate::requests::WebsocketResponse;
 use crate::Services;
 
@@ -13,6 +14,10 @@ pub struct Player {
     pub player_id: PlayerId,
     pub game_id: GameId,
     pub hand: Vec<Card>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hand_ids: Vec<CardId>,
+    #[serde(default)]
+    pub version: u64,
     #[serde(skip)]
     _private: PhantomData<()>,
 }
@@ -24,6 +29,10 @@ impl Key for Player {
     fn prefix() -> &'static str {
         "game:player"
     }
+
+    fn version(value: &Self::Value) -> Option<u64> {
+        Some(value.version)
+    }
 }
 
 impl Player {
@@ -32,15 +41,32 @@ impl Player {
         player_id: PlayerId,
         game_id: GameId,
     ) -> Result<Self, Error> {
-        let player = Player {
+        let mut player = Self::empty(player_id, game_id);
+        player.save(services).await?;
+        Ok(player)
+    }
+
+    pub async fn save(&mut self, services: &Services) -> Result<(), Error> {
+        self.version += 1;
+        services.put::<Player>(&self.player_id, self).await
+    }
+
+    pub(super) fn empty(player_id: PlayerId, game_id: GameId) -> Self {
+        Player {
             player_id,
             game_id,
             hand: vec![],
+            hand_ids: vec![],
+            version: 0,
             _private: PhantomData
-        };
+        }
+    }
 
-        let _ = services.put::<Player>(&player.player_id, &player).await?;
-        Ok(player)
+    /// Starts the player on an empty hand in another game. The version is kept, so the stored
+    /// player can still be replaced
+    pub(super) fn move_to(&mut self, game_id: GameId) {
+        self.game_id = game_id;
+        self.take_hand();
     }
 
     fn state(&self) -> WebsocketResponse {
@@ -48,15 +74,33 @@ impl Player {
         WebsocketResponse::PlayerState {
             game_id: self.game_id.clone(),
             hand: self.hand.clone(),
+            card_ids: (!self.hand_ids.is_empty()).then(|| self.hand_ids.clone()),
+        }
+    }
+
+    pub(super) fn take(&mut self, hand_index: usize) -> Option<(Card, Option<CardId>)> {
+        if hand_index >= self.hand.len() {
+            return None
         }
+        let card = self.hand.swap_remove(hand_index);
+        let id = (hand_index < self.hand_ids.len()).then(|| self.hand_ids.swap_remove(hand_index));
+        Some((card, id))
+    }
+
+    pub(super) fn give(&mut self, card: Card, id: Option<CardId>) {
+        self.hand.push(card);
+        self.hand_ids.extend(id);
+    }
+
+    pub(super) fn take_hand(&mut self) -> (Vec<Card>, Vec<CardId>) {
+        (std::mem::take(&mut self.hand), std::mem::take(&mut self.hand_ids))
     }
 
     pub async fn get_game(&self, services: &Services) -> Game {
         services.get::<Game>(&self.game_id).await.expect("player object in database after game destroyed")
     }
 
-    /// Sends the current player state to the player
-    pub async fn send_state(&self, services: &Services, conn_id: &str) -> Result<(), Error> {
+    pub async fn send_state(&self, services: &Services, conn_id: &ConnectionId) -> Result<(), Error> {
         services.send(conn_id, &self.state()).await
     }
 }
@@ -0,0 +1,214 @@
+use std::mem;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::game::{Game, PlayerId, TeamId};
+use crate::requests::{GameStateData, WebsocketRequestDiscriminants};
+use crate::requests::WebsocketRequestDiscriminants::{LeaveSeat, SwapSeats, TakeSeat};
+use crate::{Services, WebsocketError};
+
+/// A place at the table. Seats keep their position as players come and go, so every client
+/// can draw the table the same way round
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Seat {
+    pub index: usize,
+    /// Players keep their seat while they're disconnected and still have their hand
+    pub player: Option<PlayerId>,
+    pub team: Option<TeamId>,
+}
+
+impl Game {
+    fn new_seat(&self) -> Seat {
+        let index = self.seats.len();
+        Seat { index, player: None, team: self.team_for_seat(index) }
+    }
+
+    fn seat_of(&self, player_id: &PlayerId) -> Option<usize> {
+        self.seats.iter().position(|seat| seat.player.as_ref() == Some(player_id))
+    }
+
+    pub(super) fn seat(&mut self, player_id: &PlayerId) {
+        if self.seat_of(player_id).is_none() {
+            let index = match self.seats.iter().position(|seat| seat.player.is_none()) {
+                Some(index) => index,
+                None => {
+                    let seat = self.new_seat();
+                    self.seats.push(seat);
+                    self.seats.len() - 1
+                }
+            };
+            self.seats[index].player = Some(player_id.clone());
+        }
+        self.update_seat_order();
+    }
+
+    pub(super) fn free_seat(&mut self, player_id: &PlayerId) -> bool {
+        let Some(index) = self.seat_of(player_id) else {
+            return false
+        };
+        self.seats[index].player = None;
+        self.update_seat_order();
+        true
+    }
+
+    /// Works out the seat order again after players have moved or left. If the player whose
+    /// turn it was is no longer in it, the turn passes to the next seat. The first player seated
+    /// takes the turn
+    pub(super) fn update_seat_order(&mut self) {
+        let seat_order = self.seats.iter()
+            .filter_map(|seat| seat.player.as_ref())
+            .filter(|player| self.connected_players.contains_key(*player))
+            .cloned()
+            .collect();
+        let previous = mem::replace(&mut self.seat_order, seat_order);
+
+        if let Some(turn) = self.turn.clone().filter(|turn| !self.seat_order.contains(turn)) {
+            let index = previous.iter().position(|p| *p == turn).unwrap_or(0);
+            self.turn = previous[index..].iter()
+                .chain(&previous[..index])
+                .find(|p| self.seat_order.contains(p))
+                .cloned();
+        }
+        if self.turn.is_none() {
+            self.turn = self.seat_order.first().cloned();
+        }
+    }
+
+    fn seats_update(&self, cause_action: WebsocketRequestDiscriminants, player_id: &PlayerId) -> GameStateData {
+        GameStateData {
+            cause_action: Some(cause_action),
+            cause_player: Some(player_id.clone()),
+            seats: Some(self.seats.clone()),
+            seat_order: Some(self.seat_order.clone()),
+            turn: self.turn.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Seats are added up to the most players the game allows, or one past the last seat if
+    /// there's no limit
+    pub async fn take_seat(&mut self, services: &Services, player_id: &PlayerId, index: usize) -> Result<(), WebsocketError> {
+        let limit = self.settings.max_players.unwrap_or(self.seats.len() + 1);
+        if index >= limit {
+            return Err(WebsocketError::InvalidRequest("the seat does not exist"))
+        }
+        while self.seats.len() <= index {
+            let seat = self.new_seat();
+            self.seats.push(seat);
+        }
+        if self.seats[index].player.is_some() {
+            return Err(WebsocketError::InvalidRequest("the seat is taken"))
+        }
+
+        if let Some(current) = self.seat_of(player_id) {
+            self.seats[current].player = None;
+        }
+        self.seats[index].player = Some(player_id.clone());
+        self.update_seat_order();
+        let update = self.seats_update(TakeSeat, player_id);
+        self.commit(services, [], update).await?;
+        // The player may have moved onto a team
+        self.send_team_stacks(services, &self.team_stacks()).await?;
+        Ok(())
+    }
+
+    pub async fn leave_seat(&mut self, services: &Services, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        if !self.free_seat(player_id) {
+            return Err(WebsocketError::InvalidRequest("you are not in a seat"))
+        }
+        let update = self.seats_update(LeaveSeat, player_id);
+        self.commit(services, [], update).await?;
+        Ok(())
+    }
+
+    /// Either seat can be empty. Teams stay with their seats
+    pub async fn swap_seats(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        seat: usize,
+        with: usize,
+    ) -> Result<(), WebsocketError> {
+        if seat >= self.seats.len() || with >= self.seats.len() {
+            return Err(WebsocketError::InvalidRequest("the seat does not exist"))
+        }
+        let player = self.seats[seat].player.take();
+        self.seats[seat].player = mem::replace(&mut self.seats[with].player, player);
+        self.update_seat_order();
+        let update = self.seats_update(SwapSeats, player_id);
+        self.commit(services, [], update).await?;
+        self.send_team_stacks(services, &self.team_stacks()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::GameSettings;
+    use crate::requests::WebsocketRequest;
+    use crate::WebsocketError;
+
+    fn seated(players: &[&str]) -> Vec<String> {
+        players.iter().map(|player| player.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn players_are_seated_in_the_order_they_join() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b", "c"]).await;
+        let game = test.game().await;
+
+        assert_eq!(game.seat_order, seated(&["a", "b", "c"]));
+        assert_eq!(game.seats.len(), 3);
+        assert_eq!(game.turn.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn taking_a_seat_moves_the_player_in_the_seat_order() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b", "c"]).await;
+        test.send("a", WebsocketRequest::TakeSeat { seat: 3 }).await.unwrap();
+
+        let game = test.game().await;
+        assert_eq!(game.seat_order, seated(&["b", "c", "a"]));
+        assert_eq!(game.seats[0].player, None);
+
+        let result = test.send("a", WebsocketRequest::TakeSeat { seat: 1 }).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("the seat is taken"))));
+        let result = test.send("a", WebsocketRequest::TakeSeat { seat: 5 }).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("the seat does not exist"))));
+    }
+
+    #[tokio::test]
+    async fn seats_are_limited_to_the_most_players() {
+        let settings = GameSettings { max_players: Some(2), ..GameSettings::default() };
+        let test = TestGame::new(spades(1), settings, &["a", "b"]).await;
+
+        let result = test.send("a", WebsocketRequest::TakeSeat { seat: 2 }).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("the seat does not exist"))));
+    }
+
+    #[tokio::test]
+    async fn leaving_a_seat_passes_the_turn_on() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b", "c"]).await;
+        test.send("a", WebsocketRequest::LeaveSeat).await.unwrap();
+
+        let game = test.game().await;
+        assert_eq!(game.seat_order, seated(&["b", "c"]));
+        assert_eq!(game.turn.as_deref(), Some("b"));
+        assert!(game.connected_players.contains_key("a"));
+
+        let result = test.send("a", WebsocketRequest::LeaveSeat).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest("you are not in a seat"))));
+    }
+
+    #[tokio::test]
+    async fn only_hosts_can_swap_seats() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b", "c"]).await;
+
+        let result = test.send("b", WebsocketRequest::SwapSeats { seat: 0, with: 2 }).await;
+        assert!(matches!(result, Err(WebsocketError::NoPermission)));
+
+        test.send("a", WebsocketRequest::SwapSeats { seat: 0, with: 2 }).await.unwrap();
+        assert_eq!(test.game().await.seat_order, seated(&["c", "b", "a"]));
+    }
+}
@@ -0,0 +1,99 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::auth::Tier;
+use crate::game::{Position, TeamId};
+use crate::WebsocketError;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GameSettings {
+    /// Seconds before a disconnected player's hand is returned. Only the owner returns hands if not set
+    pub reclaim_after: Option<u64>,
+    /// If not set, each returned hand is put in a new stack
+    pub reclaim_position: Option<Position>,
+    pub turn_policy: TurnPolicy,
+    pub card_ids: bool,
+    /// Counts players who have left but still have their hand. Defaults to the owner's [`Tier`] limit
+    pub max_players: Option<usize>,
+    /// Defaults to the owner's [`Tier`] limit
+    pub max_spectators: Option<usize>,
+    pub teams: Vec<TeamId>,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            reclaim_after: Some(120),
+            reclaim_position: None,
+            turn_policy: TurnPolicy::default(),
+            card_ids: false,
+            max_players: None,
+            max_spectators: None,
+            teams: Vec::new(),
+        }
+    }
+}
+
+impl GameSettings {
+    pub fn apply_tier(&mut self, tier: Tier) -> Result<(), WebsocketError> {
+        let max_players = self.max_players.unwrap_or(tier.max_players());
+        if max_players == 0 {
+            return Err(WebsocketError::InvalidRequest("max players must be at least 1"))
+        }
+        if max_players > tier.max_players() {
+            return Err(WebsocketError::InvalidRequest("max players is more than your tier allows"))
+        }
+        let max_spectators = self.max_spectators.unwrap_or(tier.max_spectators());
+        if max_spectators > tier.max_spectators() {
+            return Err(WebsocketError::InvalidRequest("max spectators is more than your tier allows"))
+        }
+
+        self.max_players = Some(max_players);
+        self.max_spectators = Some(max_spectators);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum TurnPolicy {
+    /// Anyone can act at any time. Turns are still tracked, but only as a guide
+    #[default]
+    FreeForAll,
+    /// Only the player whose turn it is can act, and they end their own turn
+    RoundRobin,
+    /// Only the player whose turn it is can act, and only a host can move the turn on
+    OwnerControlled,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Tier;
+    use crate::game::GameSettings;
+    use crate::WebsocketError;
+
+    #[test]
+    fn limits_default_to_the_tier() {
+        let mut settings = GameSettings::default();
+        settings.apply_tier(Tier::Free).unwrap();
+        assert_eq!(settings.max_players, Some(Tier::Free.max_players()));
+        assert_eq!(settings.max_spectators, Some(Tier::Free.max_spectators()));
+
+        let mut settings = GameSettings { max_players: Some(4), ..GameSettings::default() };
+        settings.apply_tier(Tier::Free).unwrap();
+        assert_eq!(settings.max_players, Some(4));
+    }
+
+    #[test]
+    fn limits_above_the_tier_are_rejected() {
+        let mut settings = GameSettings { max_players: Some(Tier::Free.max_players() + 1), ..GameSettings::default() };
+        assert!(matches!(settings.apply_tier(Tier::Free), Err(WebsocketError::InvalidRequest(_))));
+        settings.apply_tier(Tier::Premium).unwrap();
+
+        let mut settings = GameSettings { max_spectators: Some(Tier::Free.max_spectators() + 1), ..GameSettings::default() };
+        assert!(matches!(settings.apply_tier(Tier::Free), Err(WebsocketError::InvalidRequest(_))));
+
+        let mut settings = GameSettings { max_players: Some(0), ..GameSettings::default() };
+        assert!(matches!(settings.apply_tier(Tier::Premium), Err(WebsocketError::InvalidRequest(_))));
+    }
+}
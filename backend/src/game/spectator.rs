@@ -0,0 +1,45 @@
+use anyhow::{anyhow, Error};
+use crate::broadcast::ConnectionId;
+use crate::game::{Game, PlayerId};
+use crate::requests::GameStateData;
+use crate::requests::WebsocketRequestDiscriminants::{JoinGame, LeaveGame, Ping};
+use crate::Services;
+
+impl Game {
+    /// Sorted so every update lists them in the same order
+    pub(super) fn spectator_list(&self) -> Vec<PlayerId> {
+        let mut spectators: Vec<PlayerId> = self.spectators.keys().cloned().collect();
+        spectators.sort();
+        spectators
+    }
+
+    pub async fn add_spectator(
+        &mut self,
+        services: &Services,
+        player_id: PlayerId,
+        conn_id: &ConnectionId,
+    ) -> Result<(), Error> {
+        self.spectators.insert(player_id.clone(), conn_id.clone());
+        let update = self.record(GameStateData {
+            cause_action: Some(JoinGame),
+            cause_player: Some(player_id),
+            spectators: Some(self.spectator_list()),
+            ..Default::default()
+        });
+        self.save(services).await?;
+        services.send(conn_id, &self.snapshot(Ping).with(&self.id)).await?;
+        self.send_state_all(services, &update).await
+    }
+
+    pub async fn remove_spectator(&mut self, services: &Services, player_id: PlayerId) -> Result<(), Error> {
+        if self.spectators.remove(&player_id).is_none() {
+            return Err(anyhow!("spectator not in this game"))
+        }
+        self.commit(services, [], GameStateData {
+            cause_action: Some(LeaveGame),
+            cause_player: Some(player_id),
+            spectators: Some(self.spectator_list()),
+            ..Default::default()
+        }).await
+    }
+}
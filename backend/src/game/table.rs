@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Error};
+use crate::broadcast::ConnectionId;
+use crate::game::{Game, PlayerId};
+use crate::requests::GameStateData;
+use crate::requests::WebsocketRequestDiscriminants::{JoinGame, LeaveGame, Ping, SetTableControls};
+use crate::{Services, WebsocketError};
+
+impl Game {
+    pub(super) fn table_list(&self) -> Vec<PlayerId> {
+        let mut tables: Vec<PlayerId> = self.tables.keys().cloned().collect();
+        tables.sort();
+        tables
+    }
+
+    pub fn has_table_controls(&self, player_id: &PlayerId) -> bool {
+        self.tables.contains_key(player_id) && self.table_controls.contains(player_id)
+    }
+
+    pub async fn add_table(
+        &mut self,
+        services: &Services,
+        player_id: PlayerId,
+        conn_id: &ConnectionId,
+    ) -> Result<(), Error> {
+        self.tables.insert(player_id.clone(), conn_id.clone());
+        let update = self.record(GameStateData {
+            cause_action: Some(JoinGame),
+            cause_player: Some(player_id),
+            tables: Some(self.table_list()),
+            ..Default::default()
+        });
+        self.save(services).await?;
+        services.send(conn_id, &self.snapshot(Ping).with(&self.id)).await?;
+        self.send_state_all(services, &update).await
+    }
+
+    /// Any controls the device was given are kept in case it reconnects
+    pub async fn remove_table(&mut self, services: &Services, player_id: PlayerId) -> Result<(), Error> {
+        if self.tables.remove(&player_id).is_none() {
+            return Err(anyhow!("table device not in this game"))
+        }
+        self.commit(services, [], GameStateData {
+            cause_action: Some(LeaveGame),
+            cause_player: Some(player_id),
+            tables: Some(self.table_list()),
+            ..Default::default()
+        }).await
+    }
+
+    pub async fn set_table_controls(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        device: PlayerId,
+        enabled: bool,
+    ) -> Result<(), WebsocketError> {
+        if enabled {
+            if !self.tables.contains_key(&device) {
+                return Err(WebsocketError::PlayerNotFound)
+            }
+            if !self.table_controls.contains(&device) {
+                self.table_controls.push(device);
+            }
+        } else {
+            self.table_controls.retain(|table| *table != device);
+        }
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(SetTableControls),
+            cause_player: Some(player_id.clone()),
+            table_controls: Some(self.table_controls.clone()),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Role;
+    use crate::game::testing::{conn_id, spades, TestGame};
+    use crate::game::GameSettings;
+    use crate::handlers::websocket::disconnect;
+    use crate::requests::WebsocketRequest;
+    use crate::WebsocketError;
+
+    #[tokio::test]
+    async fn table_devices_need_controls_to_change_the_table() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b"]).await;
+        test.join("t", Role::Table).await.unwrap();
+        let stack = test.game().await.stacks[0].id.clone();
+        let flip = WebsocketRequest::FlipStack { stack: stack.clone() };
+
+        let result = test.send_as("t", Role::Table, flip.clone()).await;
+        assert!(matches!(result, Err(WebsocketError::NoPermission)));
+
+        let controls = WebsocketRequest::SetTableControls { device: "t".to_string(), enabled: true };
+        assert!(matches!(test.send("b", controls.clone()).await, Err(WebsocketError::NoPermission)));
+        test.send("a", controls).await.unwrap();
+        test.send_as("t", Role::Table, flip).await.unwrap();
+
+        let result = test.send_as("t", Role::Table, WebsocketRequest::Kick { player: "b".to_string(), reclaim: false }).await;
+        assert!(matches!(result, Err(WebsocketError::NoPermission)));
+
+        let result = test.send_as("t", Role::Table, WebsocketRequest::TakeCard { stack }).await;
+        assert!(matches!(result, Err(WebsocketError::InvalidRequest(_))));
+        assert!(!test.game().await.connected_players.contains_key("t"));
+    }
+
+    #[tokio::test]
+    async fn table_devices_can_join_again_after_disconnecting() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a"]).await;
+        test.join("t", Role::Table).await.unwrap();
+        test.send("a", WebsocketRequest::SetTableControls { device: "t".to_string(), enabled: true }).await.unwrap();
+
+        disconnect(&test.services, "t".to_string(), &test.game_id, &conn_id("t").id).await.unwrap();
+        assert!(!test.game().await.tables.contains_key("t"));
+
+        test.join("t", Role::Table).await.unwrap();
+        assert!(test.game().await.has_table_controls(&"t".to_string()));
+    }
+}
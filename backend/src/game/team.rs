@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+use anyhow::Error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::broadcast::ConnectionId;
+use crate::game::{Game, PlayerId, StackId};
+use crate::requests::{GameStateData, WebsocketRequest, WebsocketResponse};
+use crate::requests::WebsocketRequestDiscriminants::{AddScore, SetSeatTeam, SetStackTeam, SetTeams};
+use crate::{Services, WebsocketError};
+
+pub type TeamId = String;
+
+/// Players are on the team of the seat they're sitting in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Team {
+    pub id: TeamId,
+    pub score: i64,
+}
+
+pub fn validate_teams(teams: &[TeamId]) -> Result<(), WebsocketError> {
+    let mut seen = HashSet::new();
+    if teams.iter().any(|team| team.is_empty() || !seen.insert(team)) {
+        return Err(WebsocketError::InvalidRequest("teams must have different names which aren't empty"))
+    }
+    Ok(())
+}
+
+impl Game {
+    pub fn team_of(&self, player_id: &PlayerId) -> Option<&TeamId> {
+        self.seats.iter()
+            .find(|seat| seat.player.as_ref() == Some(player_id))
+            .and_then(|seat| seat.team.as_ref())
+    }
+
+    /// Teams take seats in turn, so partners sit opposite each other
+    pub(super) fn team_for_seat(&self, index: usize) -> Option<TeamId> {
+        (!self.teams.is_empty()).then(|| self.teams[index % self.teams.len()].id.clone())
+    }
+
+    /// Cards can only be taken or turned over from a team's stacks by its members, but anyone
+    /// can move them or put cards on them
+    pub fn can_use_stack(&self, player_id: &PlayerId, request: &WebsocketRequest) -> bool {
+        let stack_id = match request {
+            WebsocketRequest::TakeCard { stack }
+            | WebsocketRequest::FlipCard { stack }
+            | WebsocketRequest::FlipStack { stack }
+            | WebsocketRequest::PopCard { stack }
+            | WebsocketRequest::DropStack { stack, .. }
+            | WebsocketRequest::Deal { stack, .. } => stack,
+            _ => return true,
+        };
+        match self.stacks.iter().find(|s| s.id == *stack_id).and_then(|s| s.team.as_ref()) {
+            Some(team) => self.team_of(player_id) == Some(team),
+            None => true,
+        }
+    }
+
+    /// Stacks that have been emptied are sent with no cards
+    pub(super) async fn send_team_stacks(
+        &self,
+        services: &Services,
+        stacks: &[(StackId, TeamId)],
+    ) -> Result<(), Error> {
+        for (stack_id, team) in stacks {
+            let response = self.team_stack_state(stack_id);
+            let members = self.connected_players.iter()
+                .filter(|(player_id, _)| self.team_of(player_id) == Some(team))
+                .map(|(_, conn_id)| conn_id);
+            services.send_batch(members, &response).await?;
+        }
+        Ok(())
+    }
+
+    pub(super) async fn send_team_stacks_to(
+        &self,
+        services: &Services,
+        player_id: &PlayerId,
+        conn_id: &ConnectionId,
+    ) -> Result<(), Error> {
+        let Some(team) = self.team_of(player_id) else {
+            return Ok(())
+        };
+        for (stack_id, _) in self.team_stacks().iter().filter(|(_, stack_team)| stack_team == team) {
+            services.send(conn_id, &self.team_stack_state(stack_id)).await?;
+        }
+        Ok(())
+    }
+
+    fn team_stack_state(&self, stack_id: &StackId) -> WebsocketResponse {
+        WebsocketResponse::TeamStack {
+            game_id: self.id.clone(),
+            stack_id: stack_id.clone(),
+            cards: self.stacks.iter()
+                .find(|s| s.id == *stack_id)
+                .map(|s| s.cards.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub(super) fn team_stacks(&self) -> Vec<(StackId, TeamId)> {
+        self.stacks.iter()
+            .filter_map(|s| s.team.as_ref().map(|team| (s.id.clone(), team.clone())))
+            .collect()
+    }
+
+    /// Resets every score, and stacks that belonged to a team no longer do
+    pub async fn set_teams(&mut self, services: &Services, player_id: &PlayerId, teams: Vec<TeamId>) -> Result<(), WebsocketError> {
+        validate_teams(&teams)?;
+        self.teams = teams.into_iter().map(|id| Team { id, score: 0 }).collect();
+        for index in 0..self.seats.len() {
+            self.seats[index].team = self.team_for_seat(index);
+        }
+        let mut stacks = Vec::new();
+        for stack in self.stacks.iter_mut().filter(|s| s.team.is_some()) {
+            stack.team = None;
+            stacks.push(stack.state());
+        }
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(SetTeams),
+            cause_player: Some(player_id.clone()),
+            teams: Some(self.teams.clone()),
+            seats: Some(self.seats.clone()),
+            stacks: (!stacks.is_empty()).then_some(stacks),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+
+    pub async fn set_seat_team(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        seat: usize,
+        team: Option<TeamId>,
+    ) -> Result<(), WebsocketError> {
+        if team.as_ref().is_some_and(|team| !self.teams.iter().any(|t| t.id == *team)) {
+            return Err(WebsocketError::InvalidRequest("the team does not exist"))
+        }
+        let seat = self.seats.get_mut(seat).ok_or(WebsocketError::InvalidRequest("the seat does not exist"))?;
+        seat.team = team;
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(SetSeatTeam),
+            cause_player: Some(player_id.clone()),
+            seats: Some(self.seats.clone()),
+            ..Default::default()
+        }).await?;
+        // Players on the seat's new team can now see its stacks
+        self.send_team_stacks(services, &self.team_stacks()).await?;
+        Ok(())
+    }
+
+    /// Players can only give stacks to their own team, or take them from it, unless they're a host
+    pub async fn set_stack_team(
+        &mut self,
+        services: &Services,
+        player_id: &PlayerId,
+        stack_id: StackId,
+        team: Option<TeamId>,
+    ) -> Result<(), WebsocketError> {
+        if team.as_ref().is_some_and(|team| !self.teams.iter().any(|t| t.id == *team)) {
+            return Err(WebsocketError::InvalidRequest("the team does not exist"))
+        }
+        let player_team = self.team_of(player_id).cloned();
+        let is_host = self.is_host(player_id);
+        let stack = self.get_stack(stack_id)?;
+        let allowed = |team: &Option<TeamId>| team.is_none() || *team == player_team;
+        if !is_host && (!allowed(&stack.team) || !allowed(&team)) {
+            return Err(WebsocketError::NotInTeam)
+        }
+        stack.team = team;
+        let state = stack.state();
+        // Undoing past the change could reveal the stack's cards to the wrong team
+        self.clear_journal();
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(SetStackTeam),
+            cause_player: Some(player_id.clone()),
+            stacks: Some(vec![state]),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+
+    pub async fn add_score(&mut self, services: &Services, player_id: &PlayerId, team: TeamId, points: i64) -> Result<(), WebsocketError> {
+        let team = self.teams.iter_mut()
+            .find(|t| t.id == team)
+            .ok_or(WebsocketError::InvalidRequest("the team does not exist"))?;
+        team.score = team.score.saturating_add(points);
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(AddScore),
+            cause_player: Some(player_id.clone()),
+            teams: Some(self.teams.clone()),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spade, spades, TestGame};
+    use crate::game::{Card, GameSettings};
+    use crate::requests::{WebsocketRequest, WebsocketResponse};
+    use crate::WebsocketError;
+
+    /// a and c are on red, b is on blue. The only stack belongs to red
+    async fn red_stack() -> (TestGame, String) {
+        let settings = GameSettings { teams: vec!["red".to_string(), "blue".to_string()], ..GameSettings::default() };
+        let test = TestGame::new(spades(3), settings, &["a", "b", "c"]).await;
+        let stack = test.game().await.stacks[0].id.clone();
+        for player in ["a", "b", "c"] {
+            test.responses(player);
+        }
+        test.send("a", WebsocketRequest::SetStackTeam { stack: stack.clone(), team: Some("red".to_string()) }).await.unwrap();
+        (test, stack)
+    }
+
+    fn team_stack(responses: &[WebsocketResponse]) -> Option<&Vec<Card>> {
+        responses.iter().find_map(|response| match response {
+            WebsocketResponse::TeamStack { cards, .. } => Some(cards),
+            _ => None,
+        })
+    }
+
+    #[tokio::test]
+    async fn team_stacks_are_only_shown_to_members() {
+        let (test, _) = red_stack().await;
+
+        assert_eq!(team_stack(&test.responses("c")).unwrap(), &[spade(1), spade(2), spade(3)]);
+        let responses = test.responses("b");
+        assert!(team_stack(&responses).is_none());
+        let visible = responses.iter().find_map(|response| match response {
+            WebsocketResponse::GameState { data, .. } => Some(data.stacks.as_ref()?[0].visible_card),
+            _ => None,
+        });
+        assert_eq!(visible, Some(Card::HIDDEN_CARD));
+    }
+
+    #[tokio::test]
+    async fn only_members_can_use_a_team_stack() {
+        let (test, stack) = red_stack().await;
+
+        let result = test.send("b", WebsocketRequest::TakeCard { stack: stack.clone() }).await;
+        assert!(matches!(result, Err(WebsocketError::NotInTeam)));
+        let result = test.send("b", WebsocketRequest::SetStackTeam { stack: stack.clone(), team: None }).await;
+        assert!(matches!(result, Err(WebsocketError::NotInTeam)));
+
+        test.send("c", WebsocketRequest::TakeCard { stack }).await.unwrap();
+        assert_eq!(test.hand("c").await, [spade(3)]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use crate::auth::Role;
+use crate::broadcast::{ConnectionId, RecordingBroadcaster};
+use crate::encoding::Encoding;
+use crate::game::{Access, Card, Game, GameId, GameSettings, Player};
+use crate::handlers::websocket::handle_message;
+use crate::requests::{DeckType, WebsocketRequest, WebsocketResponse};
+use crate::store::{MemoryStore, Store};
+use crate::{Services, WebsocketError};
+
+/// Spades from the ace up to `count`, so the highest is on top
+pub fn spades(count: u8) -> DeckType {
+    DeckType::Custom { stacks: vec![(1..=count).map(|rank| rank << 2).collect()] }
+}
+
+pub fn spade(rank: u8) -> Card {
+    Card::try_from(rank << 2).unwrap()
+}
+
+/// A JSON connection with the same id as the player
+pub fn conn_id(player: &str) -> ConnectionId {
+    ConnectionId::new(player, Encoding::Json)
+}
+
+/// A game kept in memory, where each player's connection id is their player id
+pub(crate) struct TestGame {
+    pub services: Services,
+    pub broadcaster: Arc<RecordingBroadcaster>,
+    pub game_id: GameId,
+}
+
+impl TestGame {
+    /// Owned by the first player, with every player joined in order
+    pub async fn new(deck_type: DeckType, settings: GameSettings, players: &[&str]) -> Self {
+        Self::with_store(Box::new(MemoryStore::default()), deck_type, settings, players).await
+    }
+
+    pub async fn with_store(store: Box<dyn Store>, deck_type: DeckType, settings: GameSettings, players: &[&str]) -> Self {
+        let broadcaster = Arc::new(RecordingBroadcaster::default());
+        let services = Services { store, broadcaster: broadcaster.clone() };
+        let game = Game::new(&services, players[0].to_string(), "test".to_string(), false, deck_type, settings, Access::Open)
+            .await
+            .unwrap();
+        let test = Self { services, broadcaster, game_id: game.id };
+        for player in players {
+            test.join(player, Role::Player).await.unwrap();
+        }
+        test
+    }
+
+    /// Players are authorized first, as if they had joined over http
+    pub async fn join(&self, player: &str, role: Role) -> Result<(), WebsocketError> {
+        if role == Role::Player {
+            self.game().await.add_authorized_player(&self.services, player.to_string()).await?;
+        }
+        self.send_as(player, role, WebsocketRequest::JoinGame).await
+    }
+
+    pub async fn send(&self, player: &str, request: WebsocketRequest) -> Result<(), WebsocketError> {
+        self.send_as(player, Role::Player, request).await
+    }
+
+    pub async fn send_as(&self, player: &str, role: Role, request: WebsocketRequest) -> Result<(), WebsocketError> {
+        handle_message(&self.services, request, player.to_string(), self.game_id.clone(), role, &conn_id(player)).await
+    }
+
+    pub async fn game(&self) -> Game {
+        self.services.get::<Game>(&self.game_id).await.unwrap()
+    }
+
+    pub async fn player(&self, player: &str) -> Option<Player> {
+        self.services.get::<Player>(&player.to_string()).await
+    }
+
+    pub async fn hand(&self, player: &str) -> Vec<Card> {
+        self.player(player).await.map(|player| player.hand).unwrap_or_default()
+    }
+
+    /// Responses sent to the player since this was last called
+    pub fn responses(&self, player: &str) -> Vec<WebsocketResponse> {
+        self.broadcaster.take_responses(player)
+    }
+}
@@ -0,0 +1,103 @@
+use crate::game::{Game, PlayerId, TurnPolicy};
+use crate::requests::GameStateData;
+use crate::requests::WebsocketRequestDiscriminants::{EndTurn, PassTurn};
+use crate::{Services, WebsocketError};
+
+impl Game {
+    pub fn can_act(&self, player_id: &PlayerId) -> bool {
+        match self.settings.turn_policy {
+            TurnPolicy::FreeForAll => true,
+            TurnPolicy::RoundRobin | TurnPolicy::OwnerControlled => self.turn.as_ref() == Some(player_id),
+        }
+    }
+
+    /// Hosts always can, so a game can't get stuck on a player that has stopped responding
+    fn can_move_turn(&self, player_id: &PlayerId) -> bool {
+        self.is_host(player_id) || match self.settings.turn_policy {
+            TurnPolicy::FreeForAll | TurnPolicy::RoundRobin => self.turn.as_ref() == Some(player_id),
+            TurnPolicy::OwnerControlled => false,
+        }
+    }
+
+    pub async fn end_turn(&mut self, services: &Services, player_id: &PlayerId) -> Result<(), WebsocketError> {
+        if !self.can_move_turn(player_id) {
+            return Err(WebsocketError::NotYourTurn)
+        }
+        let next = self.turn.as_ref()
+            .and_then(|turn| self.seat_order.iter().position(|p| p == turn))
+            .map_or(0, |index| (index + 1) % self.seat_order.len());
+        self.turn = self.seat_order.get(next).cloned();
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(EndTurn),
+            cause_player: Some(player_id.clone()),
+            turn: self.turn.clone(),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+
+    pub async fn pass_turn(&mut self, services: &Services, player_id: &PlayerId, to: PlayerId) -> Result<(), WebsocketError> {
+        if !self.can_move_turn(player_id) {
+            return Err(WebsocketError::NotYourTurn)
+        }
+        if !self.seat_order.contains(&to) {
+            return Err(WebsocketError::PlayerNotFound)
+        }
+        self.turn = Some(to);
+
+        self.commit(services, [], GameStateData {
+            cause_action: Some(PassTurn),
+            cause_player: Some(player_id.clone()),
+            turn: self.turn.clone(),
+            ..Default::default()
+        }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::{GameSettings, TurnPolicy};
+    use crate::requests::WebsocketRequest;
+    use crate::WebsocketError;
+
+    async fn game_with(turn_policy: TurnPolicy, players: &[&str]) -> TestGame {
+        TestGame::new(spades(4), GameSettings { turn_policy, ..Default::default() }, players).await
+    }
+
+    async fn flip(test: &TestGame, player: &str) -> Result<(), WebsocketError> {
+        let stack = test.game().await.stacks[0].id.clone();
+        test.send(player, WebsocketRequest::FlipCard { stack }).await
+    }
+
+    #[tokio::test]
+    async fn round_robin_only_lets_the_current_player_act() {
+        let test = game_with(TurnPolicy::RoundRobin, &["a", "b"]).await;
+        assert!(matches!(flip(&test, "b").await, Err(WebsocketError::NotYourTurn)));
+        assert!(matches!(test.send("b", WebsocketRequest::EndTurn).await, Err(WebsocketError::NotYourTurn)));
+
+        test.send("a", WebsocketRequest::EndTurn).await.unwrap();
+        assert_eq!(test.game().await.turn.as_deref(), Some("b"));
+        assert!(matches!(flip(&test, "a").await, Err(WebsocketError::NotYourTurn)));
+        flip(&test, "b").await.unwrap();
+
+        test.send("b", WebsocketRequest::EndTurn).await.unwrap();
+        assert_eq!(test.game().await.turn.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn owner_controlled_only_lets_the_owner_move_the_turn() {
+        let test = game_with(TurnPolicy::OwnerControlled, &["a", "b", "c"]).await;
+        test.send("a", WebsocketRequest::PassTurn { player: "b".to_string() }).await.unwrap();
+
+        assert!(matches!(test.send("b", WebsocketRequest::EndTurn).await, Err(WebsocketError::NotYourTurn)));
+        assert!(matches!(flip(&test, "a").await, Err(WebsocketError::NotYourTurn)));
+        assert!(matches!(flip(&test, "c").await, Err(WebsocketError::NotYourTurn)));
+        flip(&test, "b").await.unwrap();
+
+        test.send("a", WebsocketRequest::EndTurn).await.unwrap();
+        assert_eq!(test.game().await.turn.as_deref(), Some("c"));
+    }
+}
@@ -0,0 +1,3 @@
+// Request handling shared between the lambda functions and the standalone server
+pub mod http;
+pub mod websocket;
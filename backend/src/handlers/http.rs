@@ -0,0 +1,142 @@
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::auth::{generate_jwt, Role, Tier, TOKEN_EXPIRY, WEBSOCKET_TOKEN_EXPIRY};
+use crate::db_utils::RefreshToken;
+use crate::game::{find_game, list_games, read_log, validate_teams, Access, Game, LogEvent, PlayerId, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT, MAX_SAVE_ATTEMPTS};
+use crate::requests::{CreateGameRequest, GameListRequest, GameListResponse, GameLogRequest, GameLogResponse, JoinGameRequest, JoinGameResponse};
+use crate::{Services, WebsocketError};
+
+#[derive(Serialize, Deserialize)]
+pub struct AuthResponse {
+    pub access_token: String,
+    pub expires_in: u64,
+    pub refresh_token: String,
+}
+
+pub async fn guest_login(services: &Services) -> Result<AuthResponse, Error> {
+    let uuid = Uuid::new_v4().to_string();
+    let access_token = generate_jwt(&uuid, TOKEN_EXPIRY, None, Role::Player).await?;
+    let refresh_token = generate_refresh_token();
+
+    services.put::<RefreshToken>(&refresh_token, &uuid).await?;
+
+    Ok(AuthResponse {
+        access_token,
+        expires_in: TOKEN_EXPIRY,
+        refresh_token
+    })
+}
+
+/// Returns `None` if the refresh token is invalid or has already been used
+pub async fn refresh_token(services: &Services, token: &str) -> Result<Option<AuthResponse>, Error> {
+    let Ok(uuid) = services
+        .delete::<RefreshToken>(&token.to_string(), None)
+        .await
+    else {
+        return Ok(None);
+    };
+    let access_token = generate_jwt(&uuid, TOKEN_EXPIRY, None, Role::Player).await?;
+    let new_refresh_token = generate_refresh_token();
+    services
+        .put::<RefreshToken>(&new_refresh_token, &uuid)
+        .await?;
+
+    Ok(Some(AuthResponse {
+        access_token,
+        expires_in: TOKEN_EXPIRY,
+        refresh_token: new_refresh_token
+    }))
+}
+
+pub async fn create_game(
+    services: &Services,
+    uuid: PlayerId,
+    tier: Tier,
+    mut request: CreateGameRequest,
+) -> Result<JoinGameResponse, WebsocketError> {
+    request.deck_type.validate()?;
+    request.settings.apply_tier(tier)?;
+    validate_teams(&request.settings.teams)?;
+    if request.public && request.invite_only {
+        return Err(WebsocketError::InvalidRequest("an invite only game cannot be public"))
+    }
+    let access = Access::new(request.passphrase, request.invite_only)?;
+    // If player is currently in a game they will be removed when reconnecting to the websocket
+    let game = Game::new(
+        services,
+        uuid.clone(),
+        request.name,
+        request.public,
+        request.deck_type,
+        request.settings,
+        access,
+    ).await?;
+    let token = generate_jwt(uuid.as_str(), WEBSOCKET_TOKEN_EXPIRY, Some(&game.id), Role::Player).await?;
+    Ok(JoinGameResponse {
+        game_id: game.id,
+        code: game.code,
+        token
+    })
+}
+
+pub async fn join_game(
+    services: &Services,
+    uuid: PlayerId,
+    request: JoinGameRequest,
+) -> Result<JoinGameResponse, WebsocketError> {
+    let mut attempt = 1;
+    let game = loop {
+        let Some(mut game) = find_game(services, &request.game_id).await else {
+            return Err(WebsocketError::NonExistentGame(request.game_id));
+        };
+        let passphrase = request.passphrase.as_deref();
+        let invite = request.invite.as_deref();
+        match game.admit(services, uuid.clone(), request.role, passphrase, invite).await {
+            Ok(()) => break game,
+            Err(WebsocketError::Conflict) if attempt < MAX_SAVE_ATTEMPTS => attempt += 1,
+            Err(error) => return Err(error),
+        }
+    };
+    let token = generate_jwt(uuid.as_str(), WEBSOCKET_TOKEN_EXPIRY, Some(&game.id), request.role).await?;
+    Ok(JoinGameResponse {
+        game_id: game.id,
+        code: game.code,
+        token
+    })
+}
+
+/// The log reveals how the deck was shuffled, so it's only available once the game has ended,
+/// and only to the players who took part
+pub async fn game_log(
+    services: &Services,
+    uuid: PlayerId,
+    request: GameLogRequest,
+) -> Result<GameLogResponse, WebsocketError> {
+    if services.get::<Game>(&request.game_id).await.is_some() {
+        return Err(WebsocketError::InvalidRequest("the log is only available once the game has ended"))
+    }
+    let events = read_log(services, &request.game_id).await?;
+    if events.is_empty() {
+        return Err(WebsocketError::NonExistentGame(request.game_id))
+    }
+
+    let took_part = events.iter().any(|event| match event {
+        LogEvent::Created { owner, .. } => *owner == uuid,
+        LogEvent::Request { player, .. } => player.as_ref() == Some(&uuid),
+    });
+    if !took_part {
+        return Err(WebsocketError::NoPermission)
+    }
+    Ok(GameLogResponse { events })
+}
+
+pub async fn game_list(services: &Services, request: GameListRequest) -> Result<GameListResponse, WebsocketError> {
+    let limit = request.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+    let (games, next_cursor) = list_games(services, request.cursor.as_deref(), limit).await?;
+    Ok(GameListResponse { games, next_cursor })
+}
+
+pub fn generate_refresh_token() -> String {
+    format!("refresh_{}", Uuid::new_v4())
+}
@@ -0,0 +1,479 @@
+use std::sync::Arc;
+use anyhow::{anyhow, Error};
+use crate::auth::Role;
+use crate::broadcast::{ConnectionId, RecordingBroadcaster};
+use crate::db_utils::Connection;
+use crate::encoding::Encoding;
+use crate::game::{read_log, Game, GameId, LogEvent, PlayerId, MAX_SAVE_ATTEMPTS};
+use crate::requests::WebsocketResponse::{CloseGame, Success};
+use crate::requests::{WebsocketRequest, WebsocketResponse};
+use crate::store::MemoryStore;
+use crate::{Services, WebsocketError};
+
+/// Returns false if the game has closed, in which case the connection should be refused
+pub async fn connect(
+    services: &Services,
+    uuid: &PlayerId,
+    game_id: &GameId,
+    conn_id: &ConnectionId,
+) -> Result<bool, Error> {
+    if let Some(old_connection) = services.get::<Connection>(uuid).await {
+        // Try disconnect any current open connection
+        // It's okay if this is unsuccessful, the other connection will hang as there is no reference to its connection id
+        let _ = services.delete_connection(&old_connection.id).await;
+    }
+
+    if services.get::<Game>(game_id).await.is_none() {
+        return Ok(false);
+    };
+
+    // Stored with its encoding, as the query string is only available on connect
+    services.put::<Connection>(uuid, conn_id).await?;
+    Ok(true)
+}
+
+/// Uses `fallback` if the connection isn't the one stored for the player
+pub async fn connection(services: &Services, uuid: &PlayerId, conn_id: &str, fallback: Encoding) -> ConnectionId {
+    services.get::<Connection>(uuid).await
+        .filter(|connection| connection.id == conn_id)
+        .unwrap_or_else(|| ConnectionId::new(conn_id, fallback))
+}
+
+pub async fn disconnect(
+    services: &Services,
+    uuid: PlayerId,
+    game_id: &GameId,
+    conn_id: &str,
+) -> Result<(), Error> {
+    if let Some(connection) = services.get::<Connection>(&uuid).await.filter(|connection| connection.id == conn_id) {
+        let _ = services.delete::<Connection>(&uuid, Some(&connection)).await;
+    }
+
+    let mut attempt = 1;
+    loop {
+        let Some(mut game) = services.get::<Game>(game_id).await else {
+            return Ok(());
+        };
+        game.handling(Some(&uuid), &WebsocketRequest::LeaveGame);
+        // The player may have already left, or reconnected on a newer connection
+        let result = if game.spectators.get(&uuid).is_some_and(|conn| conn.id == conn_id) {
+            game.remove_spectator(services, uuid.clone()).await
+        } else if game.connected_players.get(&uuid).is_some_and(|conn| conn.id == conn_id) {
+            game.remove_player(services, uuid.clone()).await
+        } else if game.tables.get(&uuid).is_some_and(|conn| conn.id == conn_id) {
+            game.remove_table(services, uuid.clone()).await
+        } else {
+            return Ok(());
+        };
+
+        match result.map_err(WebsocketError::from) {
+            Err(WebsocketError::Conflict) if attempt < MAX_SAVE_ATTEMPTS => attempt += 1,
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// For servers which can wait until the grace period has passed
+pub async fn reclaim_expired(services: &Services, game_id: &GameId) -> Result<(), WebsocketError> {
+    let mut attempt = 1;
+    loop {
+        let Some(mut game) = services.get::<Game>(game_id).await else {
+            return Ok(());
+        };
+
+        match game.reclaim_expired(services).await {
+            Err(WebsocketError::Conflict) if attempt < MAX_SAVE_ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+pub async fn handle_request(
+    services: &Services,
+    request: Result<WebsocketRequest, WebsocketError>,
+    uuid: PlayerId,
+    game_id: GameId,
+    role: Role,
+    conn_id: &ConnectionId,
+) -> Result<(), Error> {
+    let response = match request {
+        Ok(message) => {
+            handle_message(services, message, uuid, game_id, role, conn_id).await
+        },
+        Err(error) => Err(error),
+    };
+
+    if let Err(e) = response {
+        services.send(conn_id, &e.into()).await?;
+    }
+    Ok(())
+}
+
+/// If another request changes the game first, the game is reloaded and the message applied again
+pub async fn handle_message(
+    services: &Services,
+    message: WebsocketRequest,
+    uuid: PlayerId,
+    game_id: GameId,
+    role: Role,
+    conn_id: &ConnectionId,
+) -> Result<(), WebsocketError> {
+    let mut attempt = 1;
+    loop {
+        let Some(game) = services.get::<Game>(&game_id).await else {
+            services.send(conn_id, &CloseGame).await?;
+            services.delete_connection(&conn_id.id).await?;
+            return Ok(());
+        };
+
+        match apply_message(services, game, message.clone(), uuid.clone(), role, conn_id).await {
+            Err(WebsocketError::Conflict) if attempt < MAX_SAVE_ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+async fn apply_message(
+    services: &Services,
+    mut game: Game,
+    message: WebsocketRequest,
+    uuid: PlayerId,
+    role: Role,
+    conn_id: &ConnectionId,
+) -> Result<(), WebsocketError> {
+    // There are no timers when running on lambda, so hands are reclaimed on the next message
+    game.reclaim_expired(services).await?;
+    game.handling(Some(&uuid), &message);
+
+    // join game -> only player showing in game -> join again, item not in db to delete?
+    match message {
+        WebsocketRequest::Ping => services.send(conn_id, &WebsocketResponse::Pong).await?,
+        WebsocketRequest::JoinGame => {
+            if game.banned.contains(&uuid) {
+                return Err(WebsocketError::Banned);
+            }
+            if game.connected_players.contains_key(&uuid)
+                || game.spectators.contains_key(&uuid)
+                || game.tables.contains_key(&uuid)
+            {
+                return Err(WebsocketError::AlreadyInGame);
+            }
+            game.check_room(&uuid, role)?;
+
+            match role {
+                Role::Player => {
+                    game.add_player(services, uuid, conn_id).await?;
+                }
+                Role::Spectator => game.add_spectator(services, uuid, conn_id).await?,
+                Role::Table => game.add_table(services, uuid, conn_id).await?,
+            }
+        }
+
+        // SPECTATOR ACTIONS
+        _ if game.spectators.contains_key(&uuid) => match message {
+            WebsocketRequest::LeaveGame => {
+                game.remove_spectator(services, uuid).await?;
+                services.send(conn_id, &Success).await?;
+                services.delete_connection(&conn_id.id).await?;
+            }
+            WebsocketRequest::Resync { since } => game.resync(services, &uuid, since, conn_id).await?,
+            _ => return Err(WebsocketError::Spectating),
+        },
+
+        // TABLE DEVICE ACTIONS
+        _ if game.tables.contains_key(&uuid) => match message {
+            WebsocketRequest::LeaveGame => {
+                game.remove_table(services, uuid).await?;
+                services.send(conn_id, &Success).await?;
+                services.delete_connection(&conn_id.id).await?;
+            }
+            WebsocketRequest::Resync { since } => game.resync(services, &uuid, since, conn_id).await?,
+            WebsocketRequest::TakeCard { .. }
+            | WebsocketRequest::PutCard { .. }
+            | WebsocketRequest::GivePlayer { .. } => {
+                return Err(WebsocketError::InvalidRequest("table devices have no hand"))
+            }
+            _ if !game.has_table_controls(&uuid) => return Err(WebsocketError::NoPermission),
+            _ if message.requires_turn() && !game.can_act(&game.owner) => {
+                return Err(WebsocketError::NotYourTurn)
+            }
+            _ if !game.can_use_stack(&uuid, &message) => return Err(WebsocketError::NotInTeam),
+            WebsocketRequest::Reset => game.reset(services).await?,
+            WebsocketRequest::Shuffle { stack } => game.shuffle_stack(services, stack, &uuid).await?,
+            WebsocketRequest::Deal { stack, count, starting_player } => {
+                game.deal(services, stack, &uuid, count, starting_player).await?
+            }
+            WebsocketRequest::FlipCard { stack } => game.flip_card(services, stack, &uuid).await?,
+            WebsocketRequest::FlipStack { stack } => game.flip_stack(services, stack, &uuid).await?,
+            WebsocketRequest::PopCard { stack } => game.pop_card(services, stack, &uuid).await?,
+            WebsocketRequest::MoveStack { stack, position } => {
+                game.move_stack(services, stack, position, &uuid).await?
+            }
+            WebsocketRequest::DropStack { stack, position } => {
+                game.drop_stack(services, stack, position, &uuid).await?
+            }
+            // Controls only cover the table, not who is in the game or who runs it
+            _ => return Err(WebsocketError::NoPermission),
+        },
+
+        _ => apply_player_message(services, game, message, uuid, conn_id).await?,
+    };
+    Ok(())
+}
+
+async fn apply_player_message(
+    services: &Services,
+    mut game: Game,
+    message: WebsocketRequest,
+    uuid: PlayerId,
+    conn_id: &ConnectionId,
+) -> Result<(), WebsocketError> {
+    match message {
+        // IN GAME ONLY ACTIONS
+        _ if !game.connected_players.contains_key(&uuid) => {
+            return Err(WebsocketError::NotInGame)
+        }
+        _ if message.requires_turn() && !game.can_act(&uuid) => {
+            return Err(WebsocketError::NotYourTurn)
+        }
+        _ if !game.can_use_stack(&uuid, &message) => {
+            return Err(WebsocketError::NotInTeam)
+        }
+        WebsocketRequest::LeaveGame => {
+            game.remove_player(services, uuid).await?;
+            services.send(conn_id, &Success).await?;
+            services.delete_connection(&conn_id.id).await?;
+        }
+        WebsocketRequest::TakeCard { stack } => {
+            game.take_card(services, stack, &uuid, conn_id).await?
+        }
+        WebsocketRequest::PutCard { hand_index, position, face_down} => {
+            game.put_card(services, &uuid, hand_index, position, face_down, conn_id).await?
+        }
+        WebsocketRequest::FlipCard { stack } => game.flip_card(services, stack, &uuid).await?,
+        WebsocketRequest::MoveStack { stack, position } => {
+            game.move_stack(services, stack, position, &uuid).await?
+        }
+        WebsocketRequest::FlipStack { stack } => game.flip_stack(services, stack, &uuid).await?,
+        WebsocketRequest::PopCard { stack } => game.pop_card(services, stack, &uuid).await?,
+        WebsocketRequest::DropStack { stack, position} => {
+            game.drop_stack(services, stack, position, &uuid).await?;
+        }
+        WebsocketRequest::Shuffle { stack } => game.shuffle_stack(services, stack, &uuid).await?,
+        WebsocketRequest::Resync { since } => game.resync(services, &uuid, since, conn_id).await?,
+        WebsocketRequest::Deal { stack, count, starting_player } => {
+            game.deal(services, stack, &uuid, count, starting_player).await?
+        }
+        WebsocketRequest::GivePlayer { hand_index, trade_to } => {
+            game.give_player(services, &uuid, hand_index, trade_to, conn_id).await?
+        }
+        WebsocketRequest::Undo => game.undo(services, &uuid).await?,
+        WebsocketRequest::Redo => game.redo(services, &uuid).await?,
+        WebsocketRequest::EndTurn => game.end_turn(services, &uuid).await?,
+        WebsocketRequest::PassTurn { player } => game.pass_turn(services, &uuid, player).await?,
+        WebsocketRequest::TakeSeat { seat } => game.take_seat(services, &uuid, seat).await?,
+        WebsocketRequest::LeaveSeat => game.leave_seat(services, &uuid).await?,
+        WebsocketRequest::SetStackTeam { stack, team } => game.set_stack_team(services, &uuid, stack, team).await?,
+        // OWNER ONLY ACTIONS, which co-hosts can also take
+        _ if !game.is_host(&uuid) => {
+            return Err(WebsocketError::NoPermission)
+        }
+        WebsocketRequest::Reset => {
+            game.reset(services).await?
+        }
+        WebsocketRequest::ReclaimHand { player } => game.reclaim_hand(services, player).await?,
+        WebsocketRequest::TransferOwnership { player } => game.transfer_ownership(services, &uuid, player).await?,
+        WebsocketRequest::SetCoHost { player, enabled } => {
+            game.set_co_host(services, &uuid, player, enabled).await?
+        }
+        WebsocketRequest::Kick { player, reclaim } => {
+            game.kick(services, &uuid, player, reclaim, false).await?
+        }
+        WebsocketRequest::Ban { player, reclaim } => game.kick(services, &uuid, player, reclaim, true).await?,
+        WebsocketRequest::SetTableControls { device, enabled } => {
+            game.set_table_controls(services, &uuid, device, enabled).await?
+        }
+        WebsocketRequest::CreateInvite => game.create_invite(services, conn_id).await?,
+        WebsocketRequest::SwapSeats { seat, with } => game.swap_seats(services, &uuid, seat, with).await?,
+        WebsocketRequest::SetTeams { teams } => game.set_teams(services, &uuid, teams).await?,
+        WebsocketRequest::SetSeatTeam { seat, team } => game.set_seat_team(services, &uuid, seat, team).await?,
+        WebsocketRequest::AddScore { team, points } => game.add_score(services, &uuid, team, points).await?,
+        // Handled before the message is passed on
+        WebsocketRequest::Ping | WebsocketRequest::JoinGame => {}
+    };
+    Ok(())
+}
+
+/// Fails if the replayed game logs anything different from the original
+pub async fn replay(events: &[LogEvent]) -> Result<Game, Error> {
+    let services = Services {
+        store: Box::new(MemoryStore::default()),
+        broadcaster: Arc::new(RecordingBroadcaster::default()),
+    };
+    let Some(created) = events.first() else {
+        return Err(anyhow!("the log is empty"))
+    };
+    let game_id = Game::from_log(&services, created).await?.id;
+
+    for event in &events[1..] {
+        let LogEvent::Request { player, request, update, shuffles } = event else {
+            return Err(anyhow!("the game was created more than once"))
+        };
+        let mut game = services.get::<Game>(&game_id).await
+            .ok_or(anyhow!("the game ended before update {}", event.seq()))?;
+        game.replay_shuffles(shuffles.clone());
+
+        match (player, request) {
+            (Some(player), request) => {
+                // The role comes from the token rather than the request, so it's taken from the
+                // update. Only spectators and table devices joining list the spectators or tables
+                let listed = |list: &Option<Vec<PlayerId>>| list.as_ref().is_some_and(|list| list.contains(player));
+                let role = match request {
+                    WebsocketRequest::JoinGame if listed(&update.spectators) => Role::Spectator,
+                    WebsocketRequest::JoinGame if listed(&update.tables) => Role::Table,
+                    _ => Role::Player,
+                };
+                // Players are authorized over http, which isn't logged
+                if *request == WebsocketRequest::JoinGame && role == Role::Player {
+                    game.add_authorized_player(&services, player.clone()).await?;
+                }
+                let conn_id = ConnectionId::new(player.clone(), Encoding::Json);
+                apply_message(&services, game, request.clone(), player.clone(), role, &conn_id).await?;
+            }
+            (None, WebsocketRequest::ReclaimHand { player }) => {
+                game.handling(None, request);
+                game.reclaim_hand(&services, player.clone()).await?;
+            }
+            (None, _) => return Err(anyhow!("update {} wasn't caused by a player", event.seq())),
+        }
+    }
+
+    let replayed = read_log(&services, &game_id).await?;
+    if let Some(index) = (0..events.len()).find(|&i| replayed.get(i) != Some(&events[i])) {
+        return Err(anyhow!("the replay differs from the log at update {}", events[index].seq()))
+    }
+    if replayed.len() > events.len() {
+        return Err(anyhow!("the replay logged more updates than the original"))
+    }
+    services.get::<Game>(&game_id).await.ok_or(anyhow!("the game ended during the replay"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use anyhow::Error;
+    use async_trait::async_trait;
+    use crate::auth::Role;
+    use crate::db_utils::Key;
+    use crate::game::testing::{spades, TestGame};
+    use crate::game::{read_log, Game, GameSettings};
+    use crate::handlers::websocket::replay;
+    use crate::requests::{DeckType, WebsocketRequest, WebsocketResponse};
+    use crate::store::{Append, Item, MemoryStore, Store};
+    use crate::WebsocketError;
+
+    /// Saves the game again the first time it's about to be deleted, as if another request had
+    /// just changed it
+    #[derive(Default)]
+    struct SavesBeforeDeleting {
+        store: MemoryStore,
+        saved: AtomicBool,
+    }
+
+    #[async_trait]
+    impl Store for SavesBeforeDeleting {
+        async fn put(&self, item: Item) -> Result<(), Error> {
+            self.store.put(item).await
+        }
+
+        async fn put_all(&self, items: Vec<Item>, appends: Vec<Append>) -> Result<(), Error> {
+            self.store.put_all(items, appends).await
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+            self.store.get(key).await
+        }
+
+        async fn list(&self, key: &str) -> Result<Vec<String>, Error> {
+            self.store.list(key).await
+        }
+
+        async fn delete(&self, key: &str, expected: Option<String>) -> Result<Option<String>, Error> {
+            self.store.delete(key, expected).await
+        }
+
+        async fn delete_version(&self, key: &str, version: u64) -> Result<(), Error> {
+            if key.starts_with(Game::prefix()) && !self.saved.swap(true, Ordering::SeqCst) {
+                let mut game: Game = serde_json::from_str(&self.store.get(key).await?.unwrap())?;
+                game.version += 1;
+                self.store.put(Game::item(&game.id, &game)?).await?;
+            }
+            self.store.delete_version(key, version).await
+        }
+
+        async fn query(&self, partition: &str, cursor: Option<&str>, limit: usize) -> Result<(Vec<String>, Option<String>), Error> {
+            self.store.query(partition, cursor, limit).await
+        }
+    }
+
+    #[tokio::test]
+    async fn replaying_the_log_gives_the_same_game() {
+        let test = TestGame::new(DeckType::Standard, GameSettings::default(), &["a", "b", "c"]).await;
+        let stack = test.game().await.stacks[0].id.clone();
+        test.send("a", WebsocketRequest::Shuffle { stack: stack.clone() }).await.unwrap();
+        test.send("b", WebsocketRequest::Deal { stack, count: Some(5), starting_player: None }).await.unwrap();
+        test.send("c", WebsocketRequest::LeaveGame).await.unwrap();
+
+        let events = read_log(&test.services, &test.game_id).await.unwrap();
+        let replayed = replay(&events).await.unwrap();
+
+        let game = test.game().await;
+        assert_eq!(replayed.seq, game.seq);
+        assert_eq!(replayed.seat_order, game.seat_order);
+        assert_eq!(replayed.stacks[0].cards, game.stacks[0].cards);
+        assert_eq!(replayed.stacks[0].cards.len(), 37);
+    }
+
+    #[tokio::test]
+    async fn reclaiming_a_player_with_no_hand_or_seat_is_recorded() {
+        let test = TestGame::new(spades(1), GameSettings::default(), &["a", "b"]).await;
+        test.send("b", WebsocketRequest::LeaveSeat).await.unwrap();
+        test.send("b", WebsocketRequest::LeaveGame).await.unwrap();
+        let seq = test.game().await.seq;
+
+        test.send("a", WebsocketRequest::ReclaimHand { player: "b".to_string() }).await.unwrap();
+        let game = test.game().await;
+        assert!(!game.disconnected.contains_key("b"));
+        assert_eq!(game.seq, seq + 1);
+
+        let replayed = replay(&read_log(&test.services, &test.game_id).await.unwrap()).await.unwrap();
+        assert_eq!(replayed.seq, game.seq);
+        assert_eq!(replayed.disconnected.keys().collect::<Vec<_>>(), game.disconnected.keys().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn spectators_see_the_table_but_cannot_change_it() {
+        let test = TestGame::new(spades(2), GameSettings::default(), &["a"]).await;
+        test.join("s", Role::Spectator).await.unwrap();
+
+        let game = test.game().await;
+        assert!(game.spectators.contains_key("s"));
+        assert!(!game.connected_players.contains_key("s"));
+        assert!(test.responses("s").iter().any(|response| matches!(response, WebsocketResponse::GameState { .. })));
+
+        let stack = game.stacks[0].id.clone();
+        let result = test.send_as("s", Role::Spectator, WebsocketRequest::FlipStack { stack }).await;
+        assert!(matches!(result, Err(WebsocketError::Spectating)));
+        let result = test.send_as("s", Role::Spectator, WebsocketRequest::JoinGame).await;
+        assert!(matches!(result, Err(WebsocketError::AlreadyInGame)));
+    }
+
+    #[tokio::test]
+    async fn leaving_is_retried_if_the_game_changes_before_it_ends() {
+        let store = Box::new(SavesBeforeDeleting::default());
+        let test = TestGame::with_store(store, spades(1), GameSettings::default(), &["a"]).await;
+
+        test.send("a", WebsocketRequest::LeaveGame).await.unwrap();
+        assert!(test.services.get::<Game>(&test.game_id).await.is_none());
+    }
+}
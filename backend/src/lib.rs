@@ -1,15 +1,23 @@
 use std::env;
+use std::sync::Arc;
 use anyhow::Error;
 use serde::{Serialize};
 use strum::IntoStaticStr;
 use thiserror::Error;
+use crate::broadcast::{ApiGatewayBroadcaster, Broadcaster, NoBroadcaster};
 use crate::game::{GameId};
+use crate::requests::InvalidCard;
+use crate::store::{DynamoStore, Store, StoreError};
 
 pub mod db_utils;
 pub mod requests;
 pub mod game;
 pub mod auth;
 pub mod utils;
+pub mod store;
+pub mod broadcast;
+pub mod handlers;
+pub mod encoding;
 
 #[derive(Debug, Serialize, Error, IntoStaticStr)]
 #[serde(rename_all="kebab-case")]
@@ -24,9 +32,30 @@ pub enum WebsocketError {
     #[error("you cannot do this whilst already in game")]
     AlreadyInGame,
 
-    #[error("only the game owner can perform this action")]
+    #[error("only the game owner or a co-host can perform this action")]
     NoPermission,
 
+    #[error("it is not your turn")]
+    NotYourTurn,
+
+    #[error("spectators cannot change the game")]
+    Spectating,
+
+    #[error("you have been banned from this game")]
+    Banned,
+
+    #[error("the passphrase is missing or incorrect")]
+    IncorrectPassphrase,
+
+    #[error("the game is invite only, and the invite is missing or has already been used")]
+    InvalidInvite,
+
+    #[error("the game is full")]
+    GameFull,
+
+    #[error("only members of the team can see into this stack")]
+    NotInTeam,
+
     #[error("the stack does not exist")]
     StackNotFound,
 
@@ -42,20 +71,29 @@ pub enum WebsocketError {
     #[error("{0}")]
     InvalidRequest(&'static str),
 
+    #[error("the deck contains {} invalid cards", .0.len())]
+    InvalidCards(Vec<InvalidCard>),
+
+    #[error("the game was changed by another player, try again")]
+    Conflict,
+
     #[error("Internal server error: {0}")]
     ServiceError(String)
 }
 
 impl From<Error> for WebsocketError {
     fn from(value: Error) -> Self {
-        WebsocketError::ServiceError(value.to_string())
+        match value.downcast_ref::<StoreError>() {
+            Some(StoreError::Conflict) => WebsocketError::Conflict,
+            Some(StoreError::InvalidCursor) => WebsocketError::InvalidRequest("the cursor is invalid"),
+            None => WebsocketError::ServiceError(value.to_string()),
+        }
     }
 }
 
 pub struct Services {
-    pub db: aws_sdk_dynamodb::Client,
-    pub apigw: Option<aws_sdk_apigatewaymanagement::Client>,
-    pub table_name: String,
+    pub store: Box<dyn Store>,
+    pub broadcaster: Arc<dyn Broadcaster>,
 }
 
 impl Services {
@@ -64,31 +102,20 @@ impl Services {
         let table_name = env::var("TABLE_NAME").expect("table name not set");
         let shared_conf = &aws_config::load_from_env().await;
 
-        let apigw = endpoint.map(|endpoint| {
-            let config = aws_sdk_apigatewaymanagement::config::Builder::from(shared_conf)
-                .endpoint_url(endpoint)
-                .build();
+        let broadcaster: Arc<dyn Broadcaster> = match endpoint {
+            Some(endpoint) => {
+                let config = aws_sdk_apigatewaymanagement::config::Builder::from(shared_conf)
+                    .endpoint_url(endpoint)
+                    .build();
 
-            aws_sdk_apigatewaymanagement::Client::from_conf(config)
-        });
+                Arc::new(ApiGatewayBroadcaster::new(aws_sdk_apigatewaymanagement::Client::from_conf(config)))
+            }
+            None => Arc::new(NoBroadcaster),
+        };
 
         Self {
-            db: aws_sdk_dynamodb::Client::new(shared_conf),
-            apigw,
-            table_name
+            store: Box::new(DynamoStore::new(aws_sdk_dynamodb::Client::new(shared_conf), table_name)),
+            broadcaster,
         }
     }
-
-    pub fn expect_apigw(&self) -> &aws_sdk_apigatewaymanagement::Client {
-        self.apigw.as_ref().expect("no API Gateway client initialised")
-    }
-
-    pub async fn delete_connection(&self, conn_id: &str) -> Result<(), Error> {
-        self.expect_apigw()
-            .delete_connection()
-            .connection_id(conn_id)
-            .send()
-            .await?;
-        Ok(())
-    }
 }
\ No newline at end of file
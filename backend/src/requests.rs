@@ -1,16 +1,16 @@
-use crate::game::{Card, GameId, PlayerId, Position, StackId, StackState};
-use crate::{Services, WebsocketError};
-use anyhow::Error;
-use aws_sdk_apigatewaymanagement::primitives::Blob;
-use lambda_http::{Request, RequestPayloadExt};
+use crate::game::{serialize_cards, Card, CardId, DeckPreset, DeckSpec, GameId, GameListing, GameSettings, LogEvent, PlayerId, Position, Seat, StackId, StackState, Team, TeamId, TurnPolicy};
+use crate::auth::Role;
+use crate::encoding::Encoding;
+use crate::WebsocketError;
+use lambda_http::{Body, Request};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use strum::EnumDiscriminants;
 use crate::requests::WebsocketResponse::GameState;
 
-#[derive(Debug, EnumDiscriminants, Deserialize, JsonSchema)]
-#[strum_discriminants(derive(Serialize, JsonSchema))]
+#[derive(Debug, Clone, PartialEq, EnumDiscriminants, Serialize, Deserialize, JsonSchema)]
+#[strum_discriminants(derive(Serialize, Deserialize, JsonSchema))]
 #[strum_discriminants(serde(rename_all = "kebab-case"))]
 #[serde(tag = "type")]
 #[serde(rename_all = "kebab-case")]
@@ -25,11 +25,43 @@ pub enum WebsocketRequest {
     MoveStack { stack: StackId, position: Position },
     DropStack { stack: StackId, position: Position },
     Shuffle { stack: StackId },
-    Deal { stack: StackId },
-    GivePlayer { hand_index: usize, trade_to: PlayerId},
+    /// Deals `count` cards to each connected player, or the whole stack if no count is given
+    Deal { stack: StackId, count: Option<usize>, starting_player: Option<PlayerId> },
+    GivePlayer { hand_index: usize, trade_to: PlayerId },
+    /// Returns a disconnected player's hand to the table straight away. The game state update
+    /// has the player whose hand was returned as the cause player
+    ReclaimHand { player: PlayerId },
+    EndTurn,
+    TakeSeat { seat: usize },
+    /// The player isn't dealt to or given turns until they take a seat again
+    LeaveSeat,
+    SwapSeats { seat: usize, with: usize },
+    SetTeams { teams: Vec<TeamId> },
+    SetSeatTeam { seat: usize, team: Option<TeamId> },
+    /// Only members of the team can see the cards, or anyone if `team` isn't set
+    SetStackTeam { stack: StackId, team: Option<TeamId> },
+    AddScore { team: TeamId, points: i64 },
+    PassTurn { player: PlayerId },
+    /// Reverts the latest table action. Players can only undo their own, hosts can undo any
+    Undo,
+    Redo,
+    TransferOwnership { player: PlayerId },
+    SetCoHost { player: PlayerId, enabled: bool },
+    /// Removes a player, spectator or table device from the game, although they can join again.
+    /// If `reclaim` is set the player's hand is put face down on the table, otherwise they keep
+    /// it. The game state update has the removed player as the cause player
+    Kick { player: PlayerId, #[serde(default)] reclaim: bool },
+    /// Kicks the player and stops them joining the game again. If `reclaim` isn't set their hand
+    /// is discarded
+    Ban { player: PlayerId, #[serde(default)] reclaim: bool },
+    /// Lets a table device reset, shuffle, deal and move stacks
+    SetTableControls { device: PlayerId, enabled: bool },
+    CreateInvite,
     Reset,
     LeaveGame,
     Ping,
+    /// Requests every update after `since`, for clients that have noticed a gap in sequence numbers
+    Resync { since: u64 },
 }
 
 // todo move into an enum
@@ -37,60 +69,178 @@ pub enum WebsocketRequest {
 #[serde(rename_all = "camelCase")]
 pub struct CreateGameRequest {
     pub name: String,
+    #[serde(default)]
+    pub public: bool,
     pub deck_type: DeckType,
+    #[serde(default)]
+    pub settings: GameSettings,
+    /// Players need this to join the game, it's only stored hashed
+    pub passphrase: Option<String>,
+    #[serde(default)]
+    pub invite_only: bool,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinGameRequest {
+    /// The game's id or its room code
     pub game_id: GameId,
+    #[serde(default)]
+    pub role: Role,
+    pub passphrase: Option<String>,
+    pub invite: Option<String>,
 }
 
 #[derive(Debug, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinGameResponse {
     pub game_id: String,
+    pub code: String,
     pub token: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GameLogRequest {
+    pub game_id: GameId,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GameLogResponse {
+    pub events: Vec<LogEvent>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GameListRequest {
+    /// The `nextCursor` of the previous page, or the first page if not given
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GameListResponse {
+    /// Public games with a free seat, newest first
+    pub games: Vec<GameListing>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag="type")]
 #[serde(rename_all = "kebab-case")]
 #[serde(rename_all_fields = "camelCase")]
 pub enum DeckType {
     Standard,
-    Custom { stacks: Vec<Vec<Card>> },
+    Spec(DeckSpec),
+    Preset { preset: DeckPreset },
+    /// Stacks of card bytes, bottom card first, see [`Card`]
+    Custom { stacks: Vec<Vec<u8>> },
+}
+
+impl DeckType {
+    pub fn validate(&self) -> Result<(), WebsocketError> {
+        let stacks = match self {
+            DeckType::Spec(spec) => return spec.validate(),
+            DeckType::Custom { stacks } => stacks,
+            DeckType::Standard | DeckType::Preset { .. } => return Ok(()),
+        };
+
+        let invalid: Vec<InvalidCard> = stacks.iter()
+            .enumerate()
+            .flat_map(|(stack, cards)| cards.iter()
+                .enumerate()
+                .filter(|(_, byte)| !Card::try_from(**byte).is_ok_and(|card| card != Card::HIDDEN_CARD))
+                .map(move |(index, byte)| InvalidCard { stack, index, byte: *byte }))
+            .collect();
+
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(WebsocketError::InvalidCards(invalid))
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct InvalidCard {
+    pub stack: usize,
+    pub index: usize,
+    pub byte: u8,
 }
 
 impl TryFrom<Request> for WebsocketRequest {
     type Error = WebsocketError;
 
+    /// Binary frames are decoded as MessagePack, text frames as JSON
     fn try_from(value: Request) -> Result<Self, Self::Error> {
-        let Ok(message) = value.json() else {
-            return Err(WebsocketError::InvalidRequest("error parsing json"));
+        let (encoding, body): (Encoding, &[u8]) = match value.body() {
+            Body::Text(text) => (Encoding::Json, text.as_bytes()),
+            Body::Binary(bytes) => (Encoding::MessagePack, bytes),
+            Body::Empty => return Err(WebsocketError::InvalidRequest("no payload sent")),
         };
+        WebsocketRequest::decode(encoding, body)
+    }
+}
 
-        match message {
-            Some(msg) => Ok(msg),
-            None => Err(WebsocketError::InvalidRequest("no payload sent")),
-        }
+impl WebsocketRequest {
+    pub fn requires_turn(&self) -> bool {
+        matches!(
+            self,
+            WebsocketRequest::TakeCard { .. }
+                | WebsocketRequest::PutCard { .. }
+                | WebsocketRequest::FlipCard { .. }
+                | WebsocketRequest::FlipStack { .. }
+                | WebsocketRequest::PopCard { .. }
+                | WebsocketRequest::MoveStack { .. }
+                | WebsocketRequest::DropStack { .. }
+                | WebsocketRequest::Shuffle { .. }
+                | WebsocketRequest::Deal { .. }
+                | WebsocketRequest::GivePlayer { .. }
+        )
+    }
+
+    pub fn decode(encoding: Encoding, bytes: &[u8]) -> Result<Self, WebsocketError> {
+        encoding.decode(bytes).map_err(|_| match encoding {
+            Encoding::Json => WebsocketError::InvalidRequest("error parsing json"),
+            Encoding::MessagePack => WebsocketError::InvalidRequest("error parsing msgpack"),
+        })
     }
 }
 
 // todo documentation for what each Stack is in stacks
-#[derive(Debug, Default, Serialize, PartialEq, JsonSchema)]
-#[serde(rename_all = "camelCase")]
+/// An update to the table. Only the fields that changed are sent, unless it is a snapshot
 #[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct GameStateData {
+    /// Increases by one with every update, so clients can detect when they have missed one
+    pub seq: u64,
+    pub snapshot: Option<bool>,
     pub cause_action: Option<WebsocketRequestDiscriminants>,
     pub cause_player: Option<PlayerId>,
+    pub code: Option<String>,
+    pub name: Option<String>,
     pub owner: Option<PlayerId>,
+    pub co_hosts: Option<Vec<PlayerId>>,
     pub players: Option<Vec<PlayerId>>,
+    pub spectators: Option<Vec<PlayerId>>,
+    pub tables: Option<Vec<PlayerId>>,
+    pub table_controls: Option<Vec<PlayerId>>,
     pub stacks: Option<Vec<StackState>>,
+    /// Every seat at the table, including empty ones. Players are on the team of their seat
+    pub seats: Option<Vec<Seat>>,
+    pub teams: Option<Vec<Team>>,
+    /// Connected players in the order they take turns
+    pub seat_order: Option<Vec<PlayerId>>,
+    pub turn: Option<PlayerId>,
+    pub turn_policy: Option<TurnPolicy>,
+    pub max_players: Option<usize>,
+    pub max_spectators: Option<usize>,
 }
 
-// todo only send update not whole state
-#[derive(Debug, Serialize, PartialEq, JsonSchema)]
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
 #[serde(tag = "type")]
 #[serde(rename_all = "kebab-case")]
 #[serde(rename_all_fields = "camelCase")]
@@ -98,17 +248,36 @@ pub enum WebsocketResponse {
     GameState {
         game_id: GameId,
         #[serde(flatten)]
-        data: GameStateData
+        data: Box<GameStateData>
     },
     PlayerState {
         game_id: GameId,
+        #[serde(serialize_with = "serialize_cards")]
         hand: Vec<Card>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        card_ids: Option<Vec<CardId>>,
     },
     Error {
         error: &'static str,
         message: String,
     },
     CloseGame,
+    /// Sent to a player who has been kicked or banned, before their connection is closed
+    Kicked {
+        game_id: GameId,
+        banned: bool,
+    },
+    /// The cards in a team's stack, from the bottom up, sent only to the team's members
+    TeamStack {
+        game_id: GameId,
+        stack_id: StackId,
+        #[serde(serialize_with = "serialize_cards")]
+        cards: Vec<Card>,
+    },
+    Invite {
+        game_id: GameId,
+        invite: String,
+    },
     Success,
     NoResponse,
     Pong,
@@ -118,7 +287,7 @@ impl GameStateData {
     pub fn with(self, game_id: &GameId) -> WebsocketResponse {
         GameState {
             game_id: game_id.clone(),
-            data: self,
+            data: Box::new(self),
         }
     }
 }
@@ -132,36 +301,20 @@ impl From<WebsocketError> for WebsocketResponse {
     }
 }
 
-impl Services {
-    pub async fn send<T: Serialize>(&self, conn_id: &str, data: &T) -> Result<(), Error> {
-        let state_blob = Blob::new(serde_json::to_string(data)?);
-        let _ = self
-            .expect_apigw()
-            .post_to_connection()
-            .connection_id(conn_id)
-            .data(state_blob.clone())
-            .send()
-            .await?;
-        Ok(())
-    }
+#[cfg(test)]
+mod tests {
+    use crate::requests::DeckType;
+    use crate::WebsocketError;
 
-    // todo change to unowned string
-    pub async fn send_batch<T, I>(&self, connections: I, data: &T) -> Result<(), Error>
-    where
-        T: Serialize,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-    {
-        let state_blob = Blob::new(serde_json::to_string(data)?);
-        for conn_id in connections {
-            let _ = self
-                .expect_apigw()
-                .post_to_connection()
-                .connection_id(conn_id.as_ref())
-                .data(state_blob.clone())
-                .send()
-                .await;
-        }
-        Ok(())
+    #[test]
+    fn validate_lists_every_invalid_card() {
+        let deck = DeckType::Custom { stacks: vec![vec![4, 0, 8], vec![], vec![63, 0b1000_0000]] };
+
+        let Err(WebsocketError::InvalidCards(invalid)) = deck.validate() else {
+            panic!("deck should be invalid");
+        };
+        let invalid: Vec<_> = invalid.iter().map(|card| (card.stack, card.index, card.byte)).collect();
+        assert_eq!(invalid, [(0, 1, 0), (2, 0, 63), (2, 1, 0b1000_0000)]);
+        assert!(DeckType::Custom { stacks: vec![vec![4, 0b1000_1000]] }.validate().is_ok());
     }
 }
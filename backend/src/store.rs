@@ -0,0 +1,72 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use thiserror::Error;
+
+mod dynamodb;
+mod memory;
+
+pub use dynamodb::*;
+pub use memory::*;
+
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn put(&self, item: Item) -> Result<(), Error>;
+
+    /// Either every item and append is stored or none are
+    async fn put_all(&self, items: Vec<Item>, appends: Vec<Append>) -> Result<(), Error>;
+
+    async fn get(&self, key: &str) -> Result<Option<String>, Error>;
+
+    async fn list(&self, key: &str) -> Result<Vec<String>, Error>;
+
+    /// If `expected` is given, the item is only deleted if its current value matches
+    async fn delete(&self, key: &str, expected: Option<String>) -> Result<Option<String>, Error>;
+
+    /// Only deletes the item if it's the stored version, failing with [`StoreError::Conflict`]
+    /// otherwise. Items stored without a version are treated as version 0
+    async fn delete_version(&self, key: &str, version: u64) -> Result<(), Error>;
+
+    /// Index entry values in the partition, highest sort first, starting after `cursor`
+    async fn query(&self, partition: &str, cursor: Option<&str>, limit: usize) -> Result<(Vec<String>, Option<String>), Error>;
+}
+
+#[derive(Debug)]
+pub struct Item {
+    pub key: String,
+    pub value: String,
+    /// If set, the item is only written if the stored version is the one before this
+    pub version: Option<u64>,
+    pub index: Option<IndexEntry>,
+}
+
+/// Writing an item again without an entry removes it from the index
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub partition: String,
+    pub sort: u64,
+    pub value: String,
+}
+
+fn index_cursor(sort: u64, key: &str) -> String {
+    format!("{sort}:{key}")
+}
+
+fn parse_index_cursor(cursor: &str) -> Result<(u64, &str), Error> {
+    cursor.split_once(':')
+        .and_then(|(sort, key)| Some((sort.parse().ok()?, key)))
+        .ok_or(StoreError::InvalidCursor.into())
+}
+
+#[derive(Debug)]
+pub struct Append {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("the item has been modified since it was read")]
+    Conflict,
+    #[error("the cursor is not one returned by the store")]
+    InvalidCursor,
+}
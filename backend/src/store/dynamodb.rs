@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use anyhow::Error;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::types::{AttributeValue, Put, ReturnValue, TransactWriteItem, Update};
+use aws_sdk_dynamodb::Client;
+use crate::store::{index_cursor, parse_index_cursor, Append, IndexEntry, Item, Store, StoreError};
+
+/// Sparse index on `index_pk` and `index_sk`, only holding items with an [`IndexEntry`]
+pub const INDEX_NAME: &str = "index";
+
+pub struct DynamoStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoStore {
+    pub fn new(client: Client, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+
+    fn version_condition(version: u64) -> (&'static str, Option<AttributeValue>) {
+        if version <= 1 {
+            ("attribute_not_exists(version)", None)
+        } else {
+            ("version = :previous", Some(AttributeValue::N((version - 1).to_string())))
+        }
+    }
+
+    fn index_attributes(index: Option<IndexEntry>) -> Vec<(&'static str, AttributeValue)> {
+        index.map_or_else(Vec::new, |index| vec![
+            ("index_pk", AttributeValue::S(index.partition)),
+            ("index_sk", AttributeValue::N(index.sort.to_string())),
+            ("index_value", AttributeValue::S(index.value)),
+        ])
+    }
+
+    /// Either a version check failed or another transaction wrote the same items
+    fn is_conflict(err: &TransactWriteItemsError) -> bool {
+        let TransactWriteItemsError::TransactionCanceledException(err) = err else {
+            return false
+        };
+        err.cancellation_reasons()
+            .iter()
+            .any(|reason| matches!(reason.code(), Some("ConditionalCheckFailed" | "TransactionConflict")))
+    }
+}
+
+#[async_trait]
+impl Store for DynamoStore {
+    async fn put(&self, item: Item) -> Result<(), Error> {
+        let mut request = self.client.put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(item.key))
+            .item("content", AttributeValue::S(item.value));
+        for (name, value) in Self::index_attributes(item.index) {
+            request = request.item(name, value);
+        }
+
+        if let Some(version) = item.version {
+            let (condition, previous) = Self::version_condition(version);
+            request = request
+                .item("version", AttributeValue::N(version.to_string()))
+                .condition_expression(condition)
+                .set_expression_attribute_values(previous.map(|previous| [(":previous".to_string(), previous)].into()));
+        }
+
+        match request.send().await {
+            Err(err) if err.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+                Err(StoreError::Conflict.into())
+            }
+            result => result.map(|_| ()).map_err(Error::from),
+        }
+    }
+
+    async fn put_all(&self, items: Vec<Item>, appends: Vec<Append>) -> Result<(), Error> {
+        let mut transaction = self.client.transact_write_items();
+        for item in items {
+            let mut put = Put::builder()
+                .table_name(&self.table_name)
+                .item("pk", AttributeValue::S(item.key))
+                .item("content", AttributeValue::S(item.value));
+            for (name, value) in Self::index_attributes(item.index) {
+                put = put.item(name, value);
+            }
+
+            if let Some(version) = item.version {
+                let (condition, previous) = Self::version_condition(version);
+                put = put
+                    .item("version", AttributeValue::N(version.to_string()))
+                    .condition_expression(condition)
+                    .set_expression_attribute_values(previous.map(|previous| [(":previous".to_string(), previous)].into()));
+            }
+            transaction = transaction.transact_items(TransactWriteItem::builder().put(put.build()?).build());
+        }
+
+        // A transaction can only write each item once, so values for the same list are appended together
+        let mut lists: Vec<(String, Vec<AttributeValue>)> = Vec::new();
+        for append in appends {
+            let value = AttributeValue::S(append.value);
+            match lists.iter_mut().find(|(key, _)| *key == append.key) {
+                Some((_, values)) => values.push(value),
+                None => lists.push((append.key, vec![value])),
+            }
+        }
+        for (key, values) in lists {
+            let update = Update::builder()
+                .table_name(&self.table_name)
+                .key("pk", AttributeValue::S(key))
+                .update_expression("SET entries = list_append(if_not_exists(entries, :empty), :values)")
+                .expression_attribute_values(":empty", AttributeValue::L(Vec::new()))
+                .expression_attribute_values(":values", AttributeValue::L(values));
+            transaction = transaction.transact_items(TransactWriteItem::builder().update(update.build()?).build());
+        }
+
+        match transaction.send().await {
+            Err(err) if err.as_service_error().is_some_and(Self::is_conflict) => {
+                Err(StoreError::Conflict.into())
+            }
+            result => result.map(|_| ()).map_err(Error::from),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let response = self.client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .send()
+            .await?;
+        Ok(response.item()
+            .and_then(|item| item.get("content"))
+            .and_then(|content| content.as_s().ok())
+            .cloned())
+    }
+
+    async fn list(&self, key: &str) -> Result<Vec<String>, Error> {
+        let response = self.client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .send()
+            .await?;
+        Ok(response.item()
+            .and_then(|item| item.get("entries"))
+            .and_then(|entries| entries.as_l().ok())
+            .map(|entries| entries.iter().filter_map(|entry| entry.as_s().ok()).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn delete(&self, key: &str, expected: Option<String>) -> Result<Option<String>, Error> {
+        let partial = self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .return_values(ReturnValue::AllOld);
+
+        let partial = match expected {
+            Some(value) => partial
+                .condition_expression("content = :value")
+                .expression_attribute_values(":value", AttributeValue::S(value)),
+            None => partial,
+        };
+
+        let response = partial.send().await?;
+        Ok(response.attributes()
+            .and_then(|item| item.get("content"))
+            .and_then(|content| content.as_s().ok())
+            .cloned())
+    }
+
+    async fn delete_version(&self, key: &str, version: u64) -> Result<(), Error> {
+        let request = self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()));
+
+        let request = match version {
+            0 => request.condition_expression("attribute_not_exists(version)"),
+            version => request
+                .condition_expression("version = :version")
+                .expression_attribute_values(":version", AttributeValue::N(version.to_string())),
+        };
+
+        match request.send().await {
+            Err(err) if err.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+                Err(StoreError::Conflict.into())
+            }
+            result => result.map(|_| ()).map_err(Error::from),
+        }
+    }
+
+    async fn query(&self, partition: &str, cursor: Option<&str>, limit: usize) -> Result<(Vec<String>, Option<String>), Error> {
+        let mut request = self.client
+            .query()
+            .table_name(&self.table_name)
+            .index_name(INDEX_NAME)
+            .key_condition_expression("index_pk = :partition")
+            .expression_attribute_values(":partition", AttributeValue::S(partition.to_string()))
+            .scan_index_forward(false)
+            .limit(i32::try_from(limit).unwrap_or(i32::MAX));
+
+        if let Some(cursor) = cursor {
+            let (sort, key) = parse_index_cursor(cursor)?;
+            request = request.set_exclusive_start_key(Some(HashMap::from([
+                ("pk".to_string(), AttributeValue::S(key.to_string())),
+                ("index_pk".to_string(), AttributeValue::S(partition.to_string())),
+                ("index_sk".to_string(), AttributeValue::N(sort.to_string())),
+            ])));
+        }
+
+        let response = request.send().await?;
+        let values = response.items()
+            .iter()
+            .filter_map(|item| item.get("index_value"))
+            .filter_map(|value| value.as_s().ok())
+            .cloned()
+            .collect();
+        let next = response.last_evaluated_key().and_then(|key| {
+            let sort = key.get("index_sk")?.as_n().ok()?.parse().ok()?;
+            Some(index_cursor(sort, key.get("pk")?.as_s().ok()?))
+        });
+        Ok((values, next))
+    }
+}
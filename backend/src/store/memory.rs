@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use crate::store::{index_cursor, parse_index_cursor, Append, IndexEntry, Item, Store, StoreError};
+
+/// In process store for local play and testing. Nothing is persisted once dropped
+#[derive(Default)]
+pub struct MemoryStore {
+    items: Mutex<HashMap<String, Stored>>,
+    lists: Mutex<HashMap<String, Vec<String>>>,
+}
+
+struct Stored {
+    value: String,
+    version: u64,
+    index: Option<IndexEntry>,
+}
+
+impl MemoryStore {
+    fn check_version(items: &HashMap<String, Stored>, item: &Item) -> Result<(), StoreError> {
+        let Some(version) = item.version else {
+            return Ok(())
+        };
+        let stored = items.get(&item.key).map_or(0, |stored| stored.version);
+        if stored + 1 != version {
+            return Err(StoreError::Conflict)
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn put(&self, item: Item) -> Result<(), Error> {
+        self.put_all(vec![item], Vec::new()).await
+    }
+
+    async fn put_all(&self, items: Vec<Item>, appends: Vec<Append>) -> Result<(), Error> {
+        // Holding the locks for every check and insert keeps the writes atomic
+        let mut stored = self.items.lock().unwrap();
+        let mut lists = self.lists.lock().unwrap();
+        for item in &items {
+            Self::check_version(&stored, item)?;
+        }
+        stored.extend(items.into_iter().map(|item| (item.key, Stored {
+            value: item.value,
+            version: item.version.unwrap_or(0),
+            index: item.index,
+        })));
+        for append in appends {
+            lists.entry(append.key).or_default().push(append.value);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        Ok(self.items.lock().unwrap().get(key).map(|stored| stored.value.clone()))
+    }
+
+    async fn list(&self, key: &str) -> Result<Vec<String>, Error> {
+        Ok(self.lists.lock().unwrap().get(key).cloned().unwrap_or_default())
+    }
+
+    async fn delete(&self, key: &str, expected: Option<String>) -> Result<Option<String>, Error> {
+        let mut items = self.items.lock().unwrap();
+        if let Some(expected) = expected
+            && items.get(key).map(|stored| &stored.value) != Some(&expected) {
+            return Err(anyhow!("conditional check failed for `{key}`"))
+        }
+        Ok(items.remove(key).map(|stored| stored.value))
+    }
+
+    async fn delete_version(&self, key: &str, version: u64) -> Result<(), Error> {
+        let mut items = self.items.lock().unwrap();
+        if items.get(key).map_or(0, |stored| stored.version) != version {
+            return Err(StoreError::Conflict.into())
+        }
+        items.remove(key);
+        Ok(())
+    }
+
+    async fn query(&self, partition: &str, cursor: Option<&str>, limit: usize) -> Result<(Vec<String>, Option<String>), Error> {
+        let after = cursor.map(parse_index_cursor).transpose()?;
+        let items = self.items.lock().unwrap();
+        let mut entries: Vec<(&str, &IndexEntry)> = items.iter()
+            .filter_map(|(key, stored)| Some((key.as_str(), stored.index.as_ref()?)))
+            .filter(|(_, entry)| entry.partition == partition)
+            // Ordered the same as the cursor, so ties are broken by the key
+            .filter(|(key, entry)| after.is_none_or(|after| (entry.sort, *key) < after))
+            .collect();
+        entries.sort_by(|(a_key, a), (b_key, b)| (b.sort, b_key).cmp(&(a.sort, a_key)));
+
+        let page: Vec<_> = entries.iter().take(limit).collect();
+        let next = (entries.len() > limit)
+            .then(|| page.last().map(|(key, entry)| index_cursor(entry.sort, key)))
+            .flatten();
+        Ok((page.iter().map(|(_, entry)| entry.value.clone()).collect(), next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::{IndexEntry, Item, MemoryStore, Store, StoreError};
+
+    fn item(value: &str, version: Option<u64>) -> Item {
+        Item { key: "key".to_string(), value: value.to_string(), version, index: None }
+    }
+
+    fn indexed(key: &str, sort: Option<u64>) -> Item {
+        let index = sort.map(|sort| IndexEntry { partition: "lobby".to_string(), sort, value: key.to_string() });
+        Item { key: key.to_string(), value: String::new(), version: None, index }
+    }
+
+    #[tokio::test]
+    async fn delete_only_removes_the_expected_value() {
+        let store = MemoryStore::default();
+        store.put(item("new", None)).await.unwrap();
+
+        assert!(store.delete("key", Some("old".to_string())).await.is_err());
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("new"));
+        assert_eq!(store.delete("key", Some("new".to_string())).await.unwrap().as_deref(), Some("new"));
+        assert_eq!(store.delete("key", None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn versioned_put_requires_the_previous_version() {
+        let store = MemoryStore::default();
+        store.put(item("first", Some(1))).await.unwrap();
+
+        let stale = store.put(item("stale", Some(1))).await.unwrap_err();
+        assert!(matches!(stale.downcast_ref::<StoreError>(), Some(StoreError::Conflict)));
+        assert!(store.put(item("skipped", Some(3))).await.is_err());
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("first"));
+
+        store.put(item("second", Some(2))).await.unwrap();
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn versioned_delete_requires_the_stored_version() {
+        let store = MemoryStore::default();
+        store.put(item("first", Some(1))).await.unwrap();
+
+        let stale = store.delete_version("key", 0).await.unwrap_err();
+        assert!(matches!(stale.downcast_ref::<StoreError>(), Some(StoreError::Conflict)));
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("first"));
+
+        store.delete_version("key", 1).await.unwrap();
+        assert_eq!(store.get("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn put_all_stores_nothing_if_any_item_conflicts() {
+        let store = MemoryStore::default();
+        store.put(item("first", Some(1))).await.unwrap();
+
+        let other = Item { key: "other".to_string(), value: "value".to_string(), version: Some(1), index: None };
+        assert!(store.put_all(vec![other, item("stale", Some(1))], vec![]).await.is_err());
+        assert_eq!(store.get("other").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn query_pages_through_the_index_highest_sort_first() {
+        let store = MemoryStore::default();
+        store.put_all(vec![indexed("a", Some(1)), indexed("b", Some(3)), indexed("c", Some(2)), indexed("d", None)], Vec::new()).await.unwrap();
+
+        let (page, cursor) = store.query("lobby", None, 2).await.unwrap();
+        assert_eq!(page, ["b", "c"]);
+        let (page, cursor) = store.query("lobby", cursor.as_deref(), 2).await.unwrap();
+        assert_eq!(page, ["a"]);
+        assert_eq!(cursor, None);
+
+        // Writing an item without an entry takes it out of the index
+        store.put(indexed("b", None)).await.unwrap();
+        assert_eq!(store.query("lobby", None, 10).await.unwrap().0, ["c", "a"]);
+        assert!(store.query("lobby", Some("not a cursor"), 10).await.is_err());
+    }
+}
@@ -14,7 +14,7 @@ import {
 } from 'aws-cdk-lib/aws-apigatewayv2';
 import {HttpLambdaIntegration, WebSocketLambdaIntegration} from 'aws-cdk-lib/aws-apigatewayv2-integrations';
 import * as config from '../config.json'
-import {AttributeType, BillingMode, Table} from 'aws-cdk-lib/aws-dynamodb';
+import {AttributeType, BillingMode, ProjectionType, Table} from 'aws-cdk-lib/aws-dynamodb';
 import {HttpLambdaAuthorizer, WebSocketLambdaAuthorizer} from 'aws-cdk-lib/aws-apigatewayv2-authorizers';
 
 const BASE_PATH = path.join(__dirname, '..', '..', 'backend')
@@ -31,6 +31,16 @@ export class MultiplayerCardsStack extends cdk.Stack {
       billingMode: BillingMode.PROVISIONED,
       removalPolicy: RemovalPolicy.DESTROY,
     })
+    // Sparse index of public games for the lobby, see `DynamoStore::query`
+    table.addGlobalSecondaryIndex({
+      indexName: 'index',
+      partitionKey: {name: 'index_pk', type: AttributeType.STRING},
+      sortKey: {name: 'index_sk', type: AttributeType.NUMBER},
+      projectionType: ProjectionType.INCLUDE,
+      nonKeyAttributes: ['index_value'],
+      readCapacity: 5,
+      writeCapacity: 5,
+    })
 
     const domainCert = acm.Certificate.fromCertificateArn(this, 'Cert', CERT_ARN);
 
@@ -133,6 +143,18 @@ export class MultiplayerCardsStack extends cdk.Stack {
       authorizer: httpAuthorizer,
       integration: httpIntegration,
     });
+    httpApi.addRoutes({
+      path: '/game/log',
+      methods: [ HttpMethod.GET ],
+      authorizer: httpAuthorizer,
+      integration: httpIntegration,
+    });
+    httpApi.addRoutes({
+      path: '/game/list',
+      methods: [ HttpMethod.GET ],
+      authorizer: httpAuthorizer,
+      integration: httpIntegration,
+    });
 
     new HttpStage(this, 'HttpDevStage', {
       httpApi: httpApi,